@@ -0,0 +1,350 @@
+use super::AsChars;
+use crate::error::{Error, Result};
+use crate::feature::Features;
+use crate::info::Info;
+
+/// 解析時に守らせる形態素境界の制約。位置は入力文字列のバイト位置で指定する。
+#[derive(Clone, Default)]
+pub struct Constraints {
+    boundaries: Vec<usize>,
+    spans: Vec<(usize, usize, Option<String>)>,
+}
+
+impl Constraints {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// `pos` を必ず形態素の境界にする。
+    #[inline]
+    pub fn add_boundary(&mut self, pos: usize) {
+        self.boundaries.push(pos);
+    }
+
+    /// `start..end` を一つの形態素にする。
+    /// `pos` を指定した場合は品詞がそれに一致する形態素だけを使う。
+    /// 品詞は `名詞,固有名詞` のようにカンマ区切りで上位から指定し、`*` は何にでも一致する。
+    /// 品詞に一致する単語も未知語もない場合、解析はエラーになる。
+    #[inline]
+    pub fn add_span(&mut self, start: usize, end: usize, pos: Option<&str>) {
+        self.spans.push((start, end, pos.map(str::to_owned)));
+    }
+
+    /// バイト位置での制約を `K` 単位の位置に変換する。
+    /// 位置が文の範囲外か文字の途中にある場合や、制約が矛盾する場合はエラーを返す。
+    /// まったく同じ範囲と品詞の制約は一つにまとめる。
+    pub(super) fn compile<K>(&self, sentence: &str) -> Result<Compiled>
+    where
+        for<'a> &'a str: AsChars<K>,
+    {
+        // バイト位置から K 単位の位置への対応。文字の途中は None
+        let mut units = vec![None; sentence.len() + 1];
+        let mut unit = 0;
+        let mut buf = [0; 4];
+        for (byte, ch) in sentence.char_indices() {
+            units[byte] = Some(unit);
+            let encoded: &str = ch.encode_utf8(&mut buf);
+            unit += AsChars::<K>::as_chars(&encoded).count();
+        }
+        units[sentence.len()] = Some(unit);
+        let to_unit = |byte: usize, name: &dyn Fn() -> String| match units.get(byte) {
+            Some(&Some(unit)) => Ok(unit),
+            Some(None) => Err(Error::analyze(format!("{} が文字の途中にあります", name()))),
+            None => Err(Error::analyze(format!(
+                "{} が文の範囲外です (文の長さ {} バイト)",
+                name(),
+                sentence.len()
+            ))),
+        };
+
+        let mut state = vec![State::Free; unit + 1];
+        let mut spans = vec![None; unit + 1];
+        state[0] = State::Required;
+        state[unit] = State::Required;
+        for &(start_byte, end_byte, ref pos) in &self.spans {
+            let name = || format!("範囲 {}..{}", start_byte, end_byte);
+            let conflict = || Error::analyze(format!("{} の制約が矛盾しています", name()));
+            if start_byte >= end_byte {
+                return Err(Error::analyze(format!("{} が空です", name())));
+            }
+            let (start, end) = (to_unit(start_byte, &name)?, to_unit(end_byte, &name)?);
+            let pos: Option<Vec<String>> = pos
+                .as_ref()
+                .map(|p| p.split(',').map(str::to_owned).collect());
+            match &spans[start] {
+                Some((len, other)) if *len == end - start && *other == pos => continue,
+                Some(_) => return Err(conflict()),
+                None => {}
+            }
+            for st in &mut state[start + 1..end] {
+                if *st == State::Required {
+                    return Err(conflict());
+                }
+                *st = State::Forbidden;
+            }
+            for &ix in &[start, end] {
+                if state[ix] == State::Forbidden {
+                    return Err(conflict());
+                }
+                state[ix] = State::Required;
+            }
+            spans[start] = Some((end - start, pos));
+        }
+        for &pos in &self.boundaries {
+            let name = || format!("境界 {}", pos);
+            let ix = to_unit(pos, &name)?;
+            if state[ix] == State::Forbidden {
+                let message = format!("{} が固定した範囲の途中にあります", name());
+                return Err(Error::analyze(message));
+            }
+            state[ix] = State::Required;
+        }
+
+        let mut next_required = vec![unit; unit + 1];
+        for ix in (0..unit).rev() {
+            next_required[ix] = if state[ix + 1] == State::Required {
+                ix + 1
+            } else {
+                next_required[ix + 1]
+            };
+        }
+        Ok(Compiled {
+            state,
+            next_required,
+            spans,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum State {
+    Free,
+    Required,
+    Forbidden,
+}
+
+pub(super) struct Compiled {
+    state: Vec<State>,
+    // 各位置より後ろで最初に境界となる位置
+    next_required: Vec<usize>,
+    // 各位置から始まる固定範囲の長さと品詞
+    spans: Vec<Option<(usize, Option<Vec<String>>)>>,
+}
+
+impl Compiled {
+    /// `ix` から長さ `len` の形態素が制約を満たすか
+    #[inline]
+    pub(super) fn accepts(&self, ix: usize, len: usize) -> bool {
+        self.state[ix] != State::Forbidden
+            && self.state[ix + len] != State::Forbidden
+            && self.next_required[ix] >= ix + len
+    }
+
+    /// `ix` から始まる固定範囲の長さ
+    #[inline]
+    pub(super) fn span_len(&self, ix: usize) -> Option<usize> {
+        self.spans[ix].as_ref().map(|span| span.0)
+    }
+
+    /// `ix` から長さ `len` の形態素の品詞が制約を満たすか
+    #[inline]
+    pub(super) fn accepts_info(
+        &self,
+        ix: usize,
+        len: usize,
+        features: &Features,
+        info: &Info,
+    ) -> bool {
+        match &self.spans[ix] {
+            Some((span_len, Some(pos))) if *span_len == len => match features.get(info.feature) {
+                Some(feature) => pos
+                    .iter()
+                    .zip(&feature.pos)
+                    .all(|(p, f)| p == "*" || p == f),
+                None => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analyze_constrained, Constraints, Features, Info, Matrix, PrefixMapInsert, Trie};
+    use crate::{Analyzed, Unknown};
+
+    fn matrix() -> Matrix {
+        Matrix {
+            width: 1289,
+            height: 1289,
+            cost: vec![0; 1289 * 1289],
+        }
+    }
+
+    fn words(analyzed: &Analyzed<'_, u8>) -> Vec<String> {
+        analyzed
+            .iter()
+            .map(|t| String::from_utf8_lossy(t.word).into_owned())
+            .collect()
+    }
+
+    fn dictionary(features: &mut Features) -> Trie<u8, Info> {
+        let noun = features.insert("名詞,一般,*,*,*,*,*,*,*").unwrap();
+        let verb = features.insert("動詞,自立,*,*,*,*,*,*,*").unwrap();
+        let mut dic = Trie::new();
+        dic.insert("a", Info::with_feature(1, 1, 100, noun));
+        dic.insert("b", Info::with_feature(1, 1, 100, noun));
+        dic.insert("c", Info::with_feature(1, 1, 100, noun));
+        dic.insert("ab", Info::with_feature(1, 1, 10, noun));
+        dic.insert("ab", Info::with_feature(2, 2, 50, verb));
+        dic.insert("bc", Info::with_feature(1, 1, 20, noun));
+        dic
+    }
+
+    #[test]
+    // "制約がなければ通常の解析と同じ結果になる"
+    fn test_no_constraint() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let constraints = Constraints::new();
+        let analyzed =
+            analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        assert_eq!(words(&analyzed), vec!["ab", "c"]);
+    }
+
+    #[test]
+    // "指定した位置を形態素の境界にする"
+    fn test_boundary() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(1);
+        let analyzed =
+            analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        assert_eq!(words(&analyzed), vec!["a", "bc"]);
+    }
+
+    #[test]
+    // "指定した範囲を一つの形態素にする"
+    fn test_span() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 3, None);
+        let analyzed =
+            analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        assert_eq!(words(&analyzed), vec!["abc"]);
+    }
+
+    #[test]
+    // "指定した範囲では品詞が一致する単語だけを使う"
+    fn test_span_pos() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 2, Some("動詞"));
+        let analyzed =
+            analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        assert_eq!(words(&analyzed), vec!["ab", "c"]);
+        let token = analyzed.iter().next().unwrap();
+        assert_eq!(token.id, 2);
+        assert_eq!(token.feature.unwrap().pos[0], "動詞");
+
+        // 品詞に一致する単語も未知語もなければエラーになる
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 3, Some("動詞"));
+        let err = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("0..3 に指定した品詞の単語がありません"));
+    }
+
+    #[test]
+    // "矛盾する制約はエラーになる"
+    fn test_conflict() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 2, None);
+        constraints.add_boundary(1);
+        let result = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints);
+        assert!(result.is_err());
+
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 2, None);
+        constraints.add_span(1, 3, None);
+        let err = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("範囲 1..3 の制約が矛盾しています"));
+    }
+
+    #[test]
+    // "同じ範囲と品詞の制約は一つにまとめる"
+    fn test_duplicate_span() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, 2, Some("動詞"));
+        constraints.add_span(0, 2, Some("動詞"));
+        let analyzed =
+            analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        assert_eq!(words(&analyzed), vec!["ab", "c"]);
+
+        constraints.add_span(0, 2, Some("名詞"));
+        let result = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    // "文の範囲外の位置はエラーになり、その位置をメッセージに含める"
+    fn test_out_of_range() {
+        let mut features = Features::new();
+        let dic = dictionary(&mut features);
+        let unknown = Unknown::default();
+        let mut constraints = Constraints::new();
+        constraints.add_span(1, 4, None);
+        let err = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("範囲 1..4 が文の範囲外です"));
+
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(5);
+        let err = analyze_constrained("abc", &dic, &matrix(), &unknown, &features, &constraints)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("境界 5 が文の範囲外です"));
+    }
+
+    #[test]
+    // "バイト位置で指定し、文字の途中の位置はエラーになる"
+    fn test_multibyte_offset() {
+        let features = Features::new();
+        let dic = Trie::<u16, Info>::new();
+        let unknown = Unknown::default();
+        let text = "東京都";
+        let mut constraints = Constraints::new();
+        constraints.add_span(0, "東京".len(), None);
+        let analyzed =
+            analyze_constrained(text, &dic, &matrix(), &unknown, &features, &constraints).unwrap();
+        let lens: Vec<_> = analyzed.iter().map(|t| t.word.len()).collect();
+        assert_eq!(lens, vec![2, 1]);
+
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(1);
+        let err = analyze_constrained(text, &dic, &matrix(), &unknown, &features, &constraints)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("境界 1 が文字の途中にあります"));
+    }
+}
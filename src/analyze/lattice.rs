@@ -0,0 +1,298 @@
+use std::io::{self, Write};
+
+use super::{Analyzed, Feature, IntoString, Matrix};
+
+/// ラティス上の候補。番号 0 は文頭、最後の番号は文末を表す。
+pub struct LatticeNode<'a, K> {
+    pub index: usize,
+    /// 文頭からの位置
+    pub start: usize,
+    pub word: &'a [K],
+    pub left_id: u16,
+    pub right_id: u16,
+    pub word_cost: i16,
+    /// このノードから文末までの最小コスト
+    pub best_cost: i32,
+    pub unknown: bool,
+    pub feature: Option<Feature<'a>>,
+    /// 最小コストの経路上にあるか
+    pub best: bool,
+}
+
+pub struct LatticeEdge {
+    pub from: usize,
+    pub to: usize,
+    /// 連接コスト
+    pub cost: i16,
+    pub best: bool,
+}
+
+/// 解析に使ったラティス全体。すべての候補と連接コストを持つ。
+pub struct Lattice<'a, K> {
+    pub nodes: Vec<LatticeNode<'a, K>>,
+    pub edges: Vec<LatticeEdge>,
+    pub cost: i32,
+}
+
+impl<'a, K> Lattice<'a, K> {
+    pub(super) fn new(analyzed: &'a Analyzed<'a, K>, matrix: &Matrix) -> Self {
+        let len = analyzed.nodes.len() - 1;
+
+        // 位置ごとの先頭のノード番号。文頭のノードが 0 番になる
+        let mut offsets = Vec::with_capacity(len + 1);
+        let mut count = 1;
+        for y in 0..=len {
+            offsets.push(count);
+            count += analyzed.column(y).len();
+        }
+
+        let mut best = vec![false; count];
+        best[0] = true;
+        let (mut x, mut y) = (analyzed.index as usize, 0);
+        loop {
+            best[offsets[y] + x] = true;
+            let node = &analyzed.column(y)[x];
+            if node.len == 0 {
+                break;
+            }
+            x = node.next as usize;
+            y += node.len as usize;
+        }
+
+        let mut nodes = Vec::with_capacity(count);
+        nodes.push(LatticeNode {
+            index: 0,
+            start: 0,
+            word: &analyzed.sentence[..0],
+            left_id: 0,
+            right_id: 0,
+            word_cost: 0,
+            best_cost: analyzed.cost,
+            unknown: false,
+            feature: None,
+            best: true,
+        });
+        let mut edges = vec![];
+        for (x, next) in analyzed.column(0).iter().enumerate() {
+            let to = offsets[0] + x;
+            edges.push(LatticeEdge {
+                from: 0,
+                to,
+                cost: matrix.get(0, next.id),
+                best: best[to] && x == analyzed.index as usize,
+            });
+        }
+        for y in 0..=len {
+            for (x, node) in analyzed.column(y).iter().enumerate() {
+                let index = offsets[y] + x;
+                let token = analyzed.token(node, y, node.cost);
+                nodes.push(LatticeNode {
+                    index,
+                    start: y,
+                    word: token.word,
+                    left_id: node.id,
+                    right_id: node.right_id,
+                    word_cost: node.word_cost,
+                    best_cost: node.cost,
+                    unknown: node.unknown,
+                    feature: token.feature,
+                    best: best[index],
+                });
+                if y == len {
+                    continue;
+                }
+                let next_y = y + node.len as usize;
+                for (next_x, next) in analyzed.column(next_y).iter().enumerate() {
+                    edges.push(LatticeEdge {
+                        from: index,
+                        to: offsets[next_y] + next_x,
+                        cost: matrix.get(node.right_id, next.id),
+                        best: best[index] && next_x == node.next as usize,
+                    });
+                }
+            }
+        }
+
+        Lattice {
+            nodes,
+            edges,
+            cost: analyzed.cost,
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch),
+        }
+    }
+    result
+}
+
+impl<'a, K: IntoString> Lattice<'a, K> {
+    #[inline]
+    fn surface(&self, node: &LatticeNode<'a, K>) -> String {
+        if node.index == 0 {
+            "BOS".to_owned()
+        } else if node.index == self.nodes.len() - 1 {
+            "EOS".to_owned()
+        } else {
+            K::into_string(node.word)
+        }
+    }
+
+    /// Graphviz の DOT 形式で書き出す。最小コストの経路は赤で表示する。
+    pub fn write_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "digraph lattice {{")?;
+        writeln!(out, "  rankdir=LR;")?;
+        writeln!(out, "  node [shape=box];")?;
+        for node in &self.nodes {
+            let label = format!(
+                "{}\\nid:{}/{} cost:{}",
+                escape(&self.surface(node)),
+                node.left_id,
+                node.right_id,
+                node.word_cost
+            );
+            let style = if node.best { ", color=red" } else { "" };
+            writeln!(out, "  n{} [label=\"{}\"{}];", node.index, label, style)?;
+        }
+        for edge in &self.edges {
+            let style = if edge.best { ", color=red" } else { "" };
+            writeln!(
+                out,
+                "  n{} -> n{} [label=\"{}\"{}];",
+                edge.from, edge.to, edge.cost, style
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// JSON 形式で一行に書き出す。
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "{{\"cost\":{},\"nodes\":[", self.cost)?;
+        for (ix, node) in self.nodes.iter().enumerate() {
+            if ix > 0 {
+                write!(out, ",")?;
+            }
+            write!(
+                out,
+                "{{\"index\":{},\"start\":{},\"end\":{},\"surface\":\"{}\",\"left_id\":{},\"right_id\":{},\"word_cost\":{},\"best_cost\":{},\"unknown\":{},\"best\":{}",
+                node.index,
+                node.start,
+                node.start + node.word.len(),
+                escape(&self.surface(node)),
+                node.left_id,
+                node.right_id,
+                node.word_cost,
+                node.best_cost,
+                node.unknown,
+                node.best
+            )?;
+            if let Some(feature) = &node.feature {
+                write!(out, ",\"feature\":\"{}\"", escape(feature.raw))?;
+            }
+            write!(out, "}}")?;
+        }
+        write!(out, "],\"edges\":[")?;
+        for (ix, edge) in self.edges.iter().enumerate() {
+            if ix > 0 {
+                write!(out, ",")?;
+            }
+            write!(
+                out,
+                "{{\"from\":{},\"to\":{},\"cost\":{},\"best\":{}}}",
+                edge.from, edge.to, edge.cost, edge.best
+            )?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analyze, Features, Info, Matrix, PrefixMapInsert, Trie, Unknown};
+
+    fn lattice_test<F: FnOnce(&crate::Lattice<'_, u8>)>(f: F) {
+        let mut dic = Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("b", Info::new(1, 1, 10));
+        dic.insert("ab", Info::new(1, 1, 15));
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0, 1, 2, 3],
+        };
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze::<u8, _>("ab", &dic, &matrix, &unknown, &features).unwrap();
+        f(&analyzed.lattice(&matrix));
+    }
+
+    #[test]
+    fn test_lattice() {
+        lattice_test(|lattice| {
+            // BOS, a, ab, b, EOS
+            assert_eq!(lattice.nodes.len(), 5);
+            let words: Vec<_> = lattice.nodes.iter().map(|n| n.word).collect();
+            assert_eq!(words, vec![&b""[..], b"a", b"ab", b"b", b""]);
+            let best: Vec<_> = lattice.nodes.iter().map(|n| n.best).collect();
+            assert_eq!(best, vec![true, false, true, false, true]);
+
+            let edges: Vec<_> = lattice
+                .edges
+                .iter()
+                .map(|e| (e.from, e.to, e.cost, e.best))
+                .collect();
+            assert_eq!(
+                edges,
+                vec![
+                    (0, 1, 1, false),
+                    (0, 2, 1, true),
+                    (1, 3, 3, false),
+                    (2, 4, 2, true),
+                    (3, 4, 2, false),
+                ]
+            );
+            let total: i32 = lattice
+                .edges
+                .iter()
+                .filter(|e| e.best)
+                .map(|e| e.cost as i32)
+                .sum::<i32>()
+                + lattice
+                    .nodes
+                    .iter()
+                    .filter(|n| n.best)
+                    .map(|n| n.word_cost as i32)
+                    .sum::<i32>();
+            assert_eq!(total, lattice.cost);
+        });
+    }
+
+    #[test]
+    fn test_export() {
+        lattice_test(|lattice| {
+            let mut dot = vec![];
+            lattice.write_dot(&mut dot).unwrap();
+            let dot = String::from_utf8(dot).unwrap();
+            assert!(dot.starts_with("digraph lattice {"));
+            assert!(dot.contains("n0 -> n2 [label=\"1\", color=red];"));
+            assert!(dot.contains("n4 [label=\"EOS\\nid:0/0 cost:0\", color=red];"));
+
+            let mut json = vec![];
+            lattice.write_json(&mut json).unwrap();
+            let json = String::from_utf8(json).unwrap();
+            assert!(json.starts_with("{\"cost\":18,\"nodes\":[{\"index\":0,"));
+            assert!(json.contains("\"surface\":\"ab\""));
+            assert!(json.ends_with("]}\n"));
+        });
+    }
+}
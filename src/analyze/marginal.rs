@@ -0,0 +1,202 @@
+use super::{Analyzed, Matrix, Token};
+use crate::error::{Error, Result};
+
+// コストを温度で割ったものを負の対数重みとみなし、前向き・後ろ向きアルゴリズムで
+// 各ノードを通るすべての経路の重みの和（周辺確率）を求める。
+
+#[inline]
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    let (hi, lo) = if a > b { (a, b) } else { (b, a) };
+    hi + (lo - hi).exp().ln_1p()
+}
+
+pub struct Marginals<'a, K> {
+    analyzed: &'a Analyzed<'a, K>,
+    // 文頭からの位置ごとの各ノードの周辺確率
+    probs: Vec<Vec<f64>>,
+    /// 全経路の重みの和の対数（分配関数）
+    pub log_z: f64,
+}
+
+impl<'a, K> Marginals<'a, K> {
+    pub(super) fn new(
+        analyzed: &'a Analyzed<'a, K>,
+        matrix: &Matrix,
+        temperature: f64,
+    ) -> Result<Self> {
+        if temperature.is_nan() || temperature <= 0.0 {
+            return Err(Error::analyze("温度は正の値にしてください"));
+        }
+        let len = analyzed.nodes.len() - 1;
+        let weight = |cost: i32| -(cost as f64) / temperature;
+
+        // 後ろ向き: ノードの次から文末までの対数重み（ノード自身の単語コストは含まない）
+        let mut beta: Vec<Vec<f64>> = (0..=len)
+            .map(|y| vec![f64::NEG_INFINITY; analyzed.column(y).len()])
+            .collect();
+        beta[len][0] = 0.0;
+        for y in (0..len).rev() {
+            for (x, node) in analyzed.column(y).iter().enumerate() {
+                let next_y = y + node.len as usize;
+                let mut sum = f64::NEG_INFINITY;
+                for (next, b) in analyzed.column(next_y).iter().zip(&beta[next_y]) {
+                    let cost = matrix.get(node.right_id, next.id) as i32 + next.word_cost as i32;
+                    sum = log_add(sum, weight(cost) + b);
+                }
+                beta[y][x] = sum;
+            }
+        }
+
+        // 前向き: 文頭からノードまでの対数重み（ノード自身の単語コストを含む）
+        // ends[y] には位置 y で終わるノードの (右文脈ID, 対数重み) を集める
+        let mut ends: Vec<Vec<(u16, f64)>> = vec![vec![]; len + 1];
+        ends[0].push((0, 0.0));
+        let mut probs = Vec::with_capacity(len + 1);
+        let mut log_z = f64::NEG_INFINITY;
+        for y in 0..=len {
+            let column = analyzed.column(y);
+            let mut column_probs = Vec::with_capacity(column.len());
+            for (x, node) in column.iter().enumerate() {
+                let mut alpha = f64::NEG_INFINITY;
+                for &(right_id, a) in &ends[y] {
+                    let cost = matrix.get(right_id, node.id) as i32 + node.word_cost as i32;
+                    alpha = log_add(alpha, a + weight(cost));
+                }
+                if y == len {
+                    log_z = alpha;
+                } else if alpha != f64::NEG_INFINITY {
+                    ends[y + node.len as usize].push((node.right_id, alpha));
+                }
+                column_probs.push(alpha + beta[y][x]);
+            }
+            probs.push(column_probs);
+        }
+        for column in &mut probs {
+            for p in column.iter_mut() {
+                *p = (*p - log_z).exp();
+            }
+        }
+
+        Ok(Marginals {
+            analyzed,
+            probs,
+            log_z,
+        })
+    }
+
+    /// ラティス上のすべての候補とその周辺確率を、開始位置の順に列挙する。
+    pub fn iter(&self) -> impl Iterator<Item = Marginal<'_, K>> {
+        let len = self.probs.len() - 1;
+        self.probs[..len]
+            .iter()
+            .enumerate()
+            .flat_map(move |(y, column)| {
+                let nodes = self.analyzed.column(y);
+                nodes
+                    .iter()
+                    .zip(column)
+                    .map(move |(node, &probability)| Marginal {
+                        start: y,
+                        token: self.analyzed.token(node, y, node.cost),
+                        probability,
+                    })
+            })
+    }
+
+    /// 最小コストの解析結果の各形態素の周辺確率
+    pub fn best(&self) -> Vec<f64> {
+        let mut result = vec![];
+        let mut x = self.analyzed.index as usize;
+        let mut y = 0;
+        loop {
+            let node = &self.analyzed.column(y)[x];
+            if node.len == 0 {
+                return result;
+            }
+            result.push(self.probs[y][x]);
+            x = node.next as usize;
+            y += node.len as usize;
+        }
+    }
+}
+
+pub struct Marginal<'a, K> {
+    /// 文頭からの位置
+    pub start: usize,
+    pub token: Token<'a, K>,
+    pub probability: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analyze, Features, Info, Matrix, PrefixMapInsert, Trie, Unknown};
+
+    #[test]
+    fn test_marginal() {
+        let mut dic = Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("b", Info::new(1, 1, 10));
+        dic.insert("ab", Info::new(1, 1, 20));
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0; 4],
+        };
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze::<u8, _>("ab", &dic, &matrix, &unknown, &features).unwrap();
+
+        // 二つの経路のコストが等しいので、どの候補も確率 0.5 になる
+        let marginals = analyzed.marginals(&matrix, 1.0).unwrap();
+        let probs: Vec<_> = marginals.iter().map(|m| m.probability).collect();
+        assert_eq!(probs.len(), 3);
+        for p in probs {
+            assert!((p - 0.5).abs() < 1e-9);
+        }
+        assert!((marginals.log_z - (-20.0 + 2f64.ln())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_temperature() {
+        let mut dic = Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("b", Info::new(1, 1, 10));
+        dic.insert("ab", Info::new(1, 1, 19));
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0; 4],
+        };
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze::<u8, _>("ab", &dic, &matrix, &unknown, &features).unwrap();
+
+        let best = analyzed.marginals(&matrix, 1.0).unwrap().best();
+        let expected = 1.0 / (1.0 + (-1f64).exp());
+        assert_eq!(best.len(), 1);
+        assert!((best[0] - expected).abs() < 1e-9);
+
+        // 温度を上げると確率は一様に近づく
+        let best = analyzed.marginals(&matrix, 1000.0).unwrap().best();
+        assert!((best[0] - 0.5).abs() < 1e-3);
+
+        let total: f64 = analyzed
+            .marginals(&matrix, 2.0)
+            .unwrap()
+            .iter()
+            .filter(|m| m.start == 0)
+            .map(|m| m.probability)
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        // 温度が正の値でなければエラーになる
+        for temperature in [0.0, -1.0, f64::NAN] {
+            let err = analyzed.marginals(&matrix, temperature).err().unwrap();
+            assert!(err.to_string().contains("温度は正の値にしてください"));
+        }
+    }
+}
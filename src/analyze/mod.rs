@@ -0,0 +1,478 @@
+use std::i32;
+
+use super::*;
+use crate::error::{Error, Result};
+use crate::unknown::CharType;
+
+mod constraint;
+mod lattice;
+mod marginal;
+mod nbest;
+
+pub use self::constraint::Constraints;
+pub use self::lattice::{Lattice, LatticeEdge, LatticeNode};
+pub use self::marginal::{Marginal, Marginals};
+pub use self::nbest::{NBest, Path, PathIter};
+
+use self::constraint::Compiled;
+
+struct Node {
+    id: u16,
+    right_id: u16,
+    word_cost: i16,
+    feature: u32,
+    unknown: bool,
+    cost: i32,
+    len: u32,
+    next: u32,
+}
+
+impl Node {
+    fn new(info: &Info, unknown: bool, cost: i32, len: u32, next: u32) -> Node {
+        Node {
+            id: info.left_id,
+            right_id: info.right_id,
+            word_cost: info.cost,
+            feature: info.feature,
+            unknown,
+            cost,
+            len,
+            next,
+        }
+    }
+}
+
+pub struct Analyzed<'a, K> {
+    sentence: Vec<K>,
+    // 各位置に対応する元の文字列でのバイト位置と文字位置
+    offsets: Vec<(usize, usize)>,
+    nodes: Vec<Vec<Node>>,
+    features: &'a Features,
+    unknown: &'a Unknown,
+    pub cost: i32,
+    index: u32,
+}
+
+impl<'a, K> Analyzed<'a, K> {
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K> {
+        Iter {
+            x: self.index,
+            y: 0,
+            analyzed: &self,
+        }
+    }
+
+    /// コストの小さい順に解析結果を列挙する。
+    #[inline]
+    pub fn nbest<'b>(&'b self, matrix: &'b Matrix) -> NBest<'b, K> {
+        NBest::new(self, matrix)
+    }
+
+    /// すべての候補と連接コストを持つラティスを作る。
+    #[inline]
+    pub fn lattice<'b>(&'b self, matrix: &Matrix) -> Lattice<'b, K> {
+        Lattice::new(self, matrix)
+    }
+
+    /// 各候補の周辺確率を求める。コストを `temperature` で割ったものを負の対数重みとする。
+    /// `temperature` が正の値でなければエラーを返す。
+    #[inline]
+    pub fn marginals<'b>(&'b self, matrix: &Matrix, temperature: f64) -> Result<Marginals<'b, K>> {
+        Marginals::new(self, matrix, temperature)
+    }
+
+    #[inline]
+    fn token<'b>(&'b self, node: &Node, start: usize, cost: i32) -> Token<'b, K> {
+        let features = if node.unknown {
+            self.unknown.features()
+        } else {
+            self.features
+        };
+        let end = start + node.len as usize;
+        let (byte_start, char_start) = self.offsets[start];
+        let (byte_end, char_end) = self.offsets[end];
+        Token {
+            word: &self.sentence[start..end],
+            byte_start,
+            byte_end,
+            char_start,
+            char_end,
+            id: node.id,
+            cost,
+            feature: features.get(node.feature),
+        }
+    }
+
+    /// 文頭から `pos` 番目の位置で始まるノードの一覧
+    #[inline]
+    fn column(&self, pos: usize) -> &[Node] {
+        &self.nodes[self.nodes.len() - pos - 1]
+    }
+}
+
+pub struct Token<'a, K> {
+    pub word: &'a [K],
+    /// 元の文字列でのバイト位置。`&sentence[byte_start..byte_end]` が表層形になる
+    pub byte_start: usize,
+    pub byte_end: usize,
+    /// 元の文字列での文字位置
+    pub char_start: usize,
+    pub char_end: usize,
+    pub id: u16,
+    pub cost: i32,
+    /// 素性。素性を読み込んでいない場合は `None`
+    pub feature: Option<Feature<'a>>,
+}
+
+pub struct Iter<'a, K> {
+    x: u32,
+    y: usize,
+    analyzed: &'a Analyzed<'a, K>,
+}
+
+impl<'a, K> Iterator for Iter<'a, K> {
+    type Item = Token<'a, K>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let nodes = &self.analyzed.nodes;
+        let node = &nodes[nodes.len() - self.y - 1][self.x as usize];
+        if node.len == 0 {
+            return None;
+        }
+        let index = self.y;
+        self.x = node.next;
+        self.y += node.len as usize;
+        Some(self.analyzed.token(node, index, node.cost))
+    }
+}
+
+#[inline]
+fn find_min_cost(src_id: u16, nodes: &[Node], matrix: &Matrix) -> Option<(usize, i32)> {
+    nodes
+        .iter()
+        .map(|nd| nd.cost + matrix.get(src_id, nd.id) as i32)
+        .enumerate()
+        .min_by_key(|pair| pair.1)
+}
+
+#[inline]
+fn push_nodes<'i, I: IntoIterator<Item = &'i Info>>(
+    column: &mut Vec<Node>,
+    nodes: &[Vec<Node>],
+    len: usize,
+    info_list: I,
+    unknown: bool,
+    matrix: &Matrix,
+) {
+    let search_nodes = &nodes[nodes.len() - len];
+    for info in info_list {
+        if let Some((index, min_cost)) = find_min_cost(info.right_id, search_nodes, matrix) {
+            column.push(Node::new(
+                info,
+                unknown,
+                min_cost + info.cost as i32,
+                len as u32,
+                index as u32,
+            ));
+        }
+    }
+}
+
+/// 各位置の文字種と文字の長さ。文字の途中の位置は `None` になる。
+fn char_types<K>(sentence: &str, unknown: &Unknown) -> Vec<Option<(CharType, usize)>>
+where
+    for<'a> &'a str: AsChars<K>,
+{
+    let mut types = vec![];
+    let mut buf = [0; 4];
+    for ch in sentence.chars() {
+        let encoded: &str = ch.encode_utf8(&mut buf);
+        let len = AsChars::<K>::as_chars(&encoded).count();
+        types.push(Some((unknown.char_type(ch), len)));
+        types.extend((1..len).map(|_| None));
+    }
+    types
+}
+
+/// 各位置に対応する元の文字列でのバイト位置と文字位置。文字の途中の位置はその文字の先頭になる。
+fn unit_offsets<K>(sentence: &str) -> Vec<(usize, usize)>
+where
+    for<'a> &'a str: AsChars<K>,
+{
+    let mut offsets = vec![];
+    let mut buf = [0; 4];
+    for (char_ix, (byte, ch)) in sentence.char_indices().enumerate() {
+        let encoded: &str = ch.encode_utf8(&mut buf);
+        let len = AsChars::<K>::as_chars(&encoded).count();
+        offsets.extend((0..len).map(|_| (byte, char_ix)));
+    }
+    offsets.push((sentence.len(), sentence.chars().count()));
+    offsets
+}
+
+/// `ix` から始まる未知語の長さの一覧
+///
+/// `group_ends` には各位置から同じカテゴリの文字が続く終端を記録し、
+/// 長い文字列でもまとめる処理が文の長さに比例する時間で済むようにする。
+fn unknown_lengths(
+    types: &[Option<(CharType, usize)>],
+    group_ends: &mut [Option<usize>],
+    ix: usize,
+    unknown: &Unknown,
+) -> Vec<usize> {
+    let (ty, _) = types[ix].unwrap();
+    let category = unknown.category(ty);
+    let compatible = |pos: usize| match types.get(pos) {
+        Some(Some((next, len))) if next.is_compatible(ty.primary) => Some(*len),
+        _ => None,
+    };
+
+    let mut lengths = vec![];
+    if category.group {
+        let mut pos = ix;
+        while let Some(len) = compatible(pos) {
+            match (types[pos], group_ends[pos]) {
+                (Some((next, _)), Some(end)) if pos > ix && next.primary == ty.primary => {
+                    pos = end;
+                    break;
+                }
+                _ => pos += len,
+            }
+        }
+        group_ends[ix] = Some(pos);
+        if pos > ix {
+            lengths.push(pos - ix);
+        }
+    }
+    let mut pos = ix;
+    for _ in 0..category.length {
+        match compatible(pos) {
+            Some(len) => pos += len,
+            _ => break,
+        }
+        if !lengths.contains(&(pos - ix)) {
+            lengths.push(pos - ix);
+        }
+    }
+    lengths
+}
+
+#[inline]
+pub fn analyze<'u, K, D>(
+    sentence: &str,
+    dic: &D,
+    matrix: &Matrix,
+    unknown: &'u Unknown,
+    features: &'u Features,
+) -> Result<Analyzed<'u, K>>
+where
+    for<'a> &'a str: AsChars<K>,
+    K: Copy,
+    D: PrefixMap<K, Info>,
+{
+    build_lattice(sentence, dic, matrix, unknown, features, None)
+}
+
+/// 制約を満たす中でコストが最小の解析結果を返す。
+/// 制約が矛盾している場合や、制約を満たす解析結果がない場合はエラーになる。
+#[inline]
+pub fn analyze_constrained<'u, K, D>(
+    sentence: &str,
+    dic: &D,
+    matrix: &Matrix,
+    unknown: &'u Unknown,
+    features: &'u Features,
+    constraints: &Constraints,
+) -> Result<Analyzed<'u, K>>
+where
+    for<'a> &'a str: AsChars<K>,
+    K: Copy,
+    D: PrefixMap<K, Info>,
+{
+    let compiled = constraints.compile::<K>(sentence)?;
+    build_lattice(sentence, dic, matrix, unknown, features, Some(&compiled))
+}
+
+fn build_lattice<'u, K, D>(
+    sentence: &str,
+    dic: &D,
+    matrix: &Matrix,
+    unknown: &'u Unknown,
+    features: &'u Features,
+    constraint: Option<&Compiled>,
+) -> Result<Analyzed<'u, K>>
+where
+    for<'a> &'a str: AsChars<K>,
+    K: Copy,
+    D: PrefixMap<K, Info>,
+{
+    let accepts = |ix: usize, len: usize| constraint.is_none_or(|c| c.accepts(ix, len));
+    let accepts_info = |ix: usize, len: usize, features: &Features, info: &Info| {
+        constraint.is_none_or(|c| c.accepts_info(ix, len, features, info))
+    };
+    let types = char_types::<K>(sentence, unknown);
+    let offsets = unit_offsets::<K>(sentence);
+    let sentence: Vec<K> = sentence.as_chars().collect();
+    debug_assert_eq!(types.len(), sentence.len());
+    if sentence.len() > u32::MAX as usize {
+        return Err(Error::analyze("文が長すぎます"));
+    }
+    let mut group_ends = vec![None; sentence.len()];
+    let mut nodes = Vec::with_capacity(sentence.len() + 1);
+    nodes.push(vec![Node::new(&Info::new(0, 0, 0), false, 0, 0, 0)]);
+    for ix in (0..sentence.len()).rev() {
+        debug_assert!(nodes.len() == sentence.len() - ix);
+        let mut column = vec![];
+        dic.each_prefix(&sentence[ix..], |len, info_list| {
+            if accepts(ix, len) {
+                let info_list = info_list
+                    .iter()
+                    .filter(|info| accepts_info(ix, len, features, info));
+                push_nodes(&mut column, &nodes, len, info_list, false, matrix);
+            }
+        });
+        // 未知語処理は文字の先頭でのみ行う
+        if let Some((ty, char_len)) = types[ix] {
+            let unknown_infos = |len: usize| {
+                unknown
+                    .infos(ty)
+                    .iter()
+                    .filter(move |info| accepts_info(ix, len, unknown.features(), info))
+            };
+            if unknown.category(ty).invoke || column.is_empty() {
+                for len in unknown_lengths(&types, &mut group_ends, ix, unknown) {
+                    if accepts(ix, len) {
+                        push_nodes(&mut column, &nodes, len, unknown_infos(len), true, matrix);
+                    }
+                }
+            }
+            if let Some(len) = constraint.and_then(|c| c.span_len(ix)) {
+                // 固定範囲に当てはまる単語がなければ未知語として追加する
+                if !column.iter().any(|node| node.len as usize == len) {
+                    if unknown_infos(len).next().is_none() {
+                        let (start, end) = (offsets[ix].0, offsets[ix + len].0);
+                        let message =
+                            format!("{}..{} に指定した品詞の単語がありません", start, end);
+                        return Err(Error::analyze(message));
+                    }
+                    push_nodes(&mut column, &nodes, len, unknown_infos(len), true, matrix);
+                }
+            } else if column.is_empty() && accepts(ix, char_len) {
+                push_nodes(
+                    &mut column,
+                    &nodes,
+                    char_len,
+                    unknown.infos(ty),
+                    true,
+                    matrix,
+                );
+            }
+        }
+        // 遷移先の位置は u32 で持つため、それを超える候補は扱えない
+        if column.len() > u32::MAX as usize {
+            return Err(Error::analyze("候補が多すぎます"));
+        }
+        nodes.push(column);
+    }
+    debug_assert_eq!(nodes.len(), sentence.len() + 1);
+    if let Some((index, cost)) = find_min_cost(0, nodes.last().unwrap(), matrix) {
+        Ok(Analyzed {
+            sentence,
+            offsets,
+            nodes,
+            features,
+            unknown,
+            cost,
+            index: index as u32,
+        })
+    } else {
+        Err(Error::analyze("解析結果がありません"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix() -> Matrix {
+        Matrix {
+            width: 1289,
+            height: 1289,
+            cost: vec![0; 1289 * 1289],
+        }
+    }
+
+    #[test]
+    // "255 文字を超える単語"
+    fn test_long_word() {
+        let word = "あ".repeat(300);
+        let mut dic = Trie::<u16, Info>::new();
+        dic.insert(word.as_str(), Info::new(1, 1, 10));
+        dic.insert("あ", Info::new(1, 1, 1000));
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze(&word, &dic, &matrix(), &unknown, &features).unwrap();
+        let tokens: Vec<_> = analyzed.iter().collect();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].word.len(), 300);
+        assert_eq!(analyzed.cost, 10);
+    }
+
+    #[test]
+    // "同じ位置に 256 を超える候補がある場合"
+    fn test_many_candidates() {
+        let mut dic = Trie::<u8, Info>::new();
+        for cost in (0..300).rev() {
+            dic.insert("a", Info::new(1, 1, cost + 10));
+        }
+        dic.insert("ab", Info::new(1, 1, 100));
+        dic.insert("b", Info::new(1, 1, 5));
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze("ab", &dic, &matrix(), &unknown, &features).unwrap();
+        let costs: Vec<_> = analyzed.iter().map(|t| t.cost).collect();
+        assert_eq!(analyzed.cost, 15);
+        assert_eq!(costs, vec![15, 5]);
+    }
+
+    #[test]
+    // "255 文字を超える未知語のまとまり"
+    fn test_long_group() {
+        let char_def = "DEFAULT 0 1 0\nALPHA 1 1 0\n0x0061..0x007A ALPHA\n";
+        let unk_def = "DEFAULT,1,1,1000\nALPHA,1,1,100\n";
+        let unknown = Unknown::from_reader(char_def.as_bytes(), unk_def.as_bytes()).unwrap();
+        let features = Features::new();
+        let dic = Trie::<char, Info>::new();
+        let text = "a".repeat(1000);
+        let analyzed = analyze(&text, &dic, &matrix(), &unknown, &features).unwrap();
+        let lens: Vec<_> = analyzed.iter().map(|t| t.word.len()).collect();
+        assert_eq!(lens, vec![1000]);
+    }
+
+    #[test]
+    // "元の文字列での位置"
+    fn test_offsets() {
+        fn offsets<K>(text: &str) -> Vec<(usize, usize, usize, usize)>
+        where
+            for<'a> &'a str: AsChars<K>,
+            K: Copy + Ord,
+        {
+            let mut dic = Trie::<K, Info>::new();
+            dic.insert("東京", Info::new(1, 1, 10));
+            dic.insert("𠮷野家", Info::new(1, 1, 10));
+            let (unknown, features) = (Unknown::default(), Features::new());
+            let analyzed = analyze(text, &dic, &matrix(), &unknown, &features).unwrap();
+            let result = analyzed
+                .iter()
+                .map(|t| (t.byte_start, t.byte_end, t.char_start, t.char_end))
+                .collect();
+            result
+        }
+        let text = "東京の𠮷野家";
+        let expected = vec![(0, 6, 0, 2), (6, 9, 2, 3), (9, 19, 3, 6)];
+        assert_eq!(offsets::<u8>(text), expected);
+        assert_eq!(offsets::<u16>(text), expected);
+        assert_eq!(offsets::<char>(text), expected);
+        assert_eq!(&text[9..19], "𠮷野家");
+    }
+}
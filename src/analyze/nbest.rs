@@ -0,0 +1,197 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use super::{Analyzed, Matrix, Token};
+
+// 前向き A* 探索で N-best 解を列挙する。
+// ノードの cost は文末までの最小コストなので、そのまま厳密なヒューリスティックとして使える。
+
+struct Step {
+    prev: usize,
+    x: usize,
+    y: usize,
+    // 文頭からこのノードに入るまでのコスト（連接コストを含む）
+    cost: i32,
+}
+
+#[derive(Eq, PartialEq)]
+struct State {
+    estimate: i32,
+    seq: usize,
+    step: usize,
+}
+
+impl Ord for State {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.estimate, self.seq).cmp(&(other.estimate, other.seq))
+    }
+}
+
+impl PartialOrd for State {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct NBest<'a, K> {
+    analyzed: &'a Analyzed<'a, K>,
+    matrix: &'a Matrix,
+    steps: Vec<Step>,
+    heap: BinaryHeap<Reverse<State>>,
+    seq: usize,
+}
+
+impl<'a, K> NBest<'a, K> {
+    pub(super) fn new(analyzed: &'a Analyzed<'a, K>, matrix: &'a Matrix) -> Self {
+        let mut nbest = NBest {
+            analyzed,
+            matrix,
+            steps: vec![],
+            heap: BinaryHeap::new(),
+            seq: 0,
+        };
+        for (x, node) in analyzed.column(0).iter().enumerate() {
+            let cost = matrix.get(0, node.id) as i32;
+            nbest.push(usize::MAX, x, 0, cost);
+        }
+        nbest
+    }
+
+    #[inline]
+    fn push(&mut self, prev: usize, x: usize, y: usize, cost: i32) {
+        let node = &self.analyzed.column(y)[x];
+        let step = self.steps.len();
+        self.steps.push(Step { prev, x, y, cost });
+        self.heap.push(Reverse(State {
+            estimate: cost + node.cost,
+            seq: self.seq,
+            step,
+        }));
+        self.seq += 1;
+    }
+}
+
+impl<'a, K> Iterator for NBest<'a, K> {
+    type Item = Path<'a, K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Reverse(state)) = self.heap.pop() {
+            let (x, y, cost) = {
+                let step = &self.steps[state.step];
+                (step.x, step.y, step.cost)
+            };
+            let node = &self.analyzed.column(y)[x];
+            if node.len == 0 {
+                let mut nodes = vec![];
+                let mut ix = self.steps[state.step].prev;
+                while ix != usize::MAX {
+                    let step = &self.steps[ix];
+                    nodes.push((step.x, step.y, cost - step.cost));
+                    ix = step.prev;
+                }
+                nodes.reverse();
+                return Some(Path {
+                    analyzed: self.analyzed,
+                    nodes,
+                    cost,
+                });
+            }
+            let next_y = y + node.len as usize;
+            let cost = cost + node.word_cost as i32;
+            let right_id = node.right_id;
+            for (next_x, next) in self.analyzed.column(next_y).iter().enumerate() {
+                let next_cost = cost + self.matrix.get(right_id, next.id) as i32;
+                self.push(state.step, next_x, next_y, next_cost);
+            }
+        }
+        None
+    }
+}
+
+pub struct Path<'a, K> {
+    analyzed: &'a Analyzed<'a, K>,
+    // (x, y, 文末までのコスト)
+    nodes: Vec<(usize, usize, i32)>,
+    pub cost: i32,
+}
+
+impl<'a, K> Path<'a, K> {
+    #[inline]
+    pub fn iter(&self) -> PathIter<'_, K> {
+        PathIter {
+            analyzed: self.analyzed,
+            nodes: self.nodes.iter(),
+        }
+    }
+}
+
+pub struct PathIter<'a, K> {
+    analyzed: &'a Analyzed<'a, K>,
+    nodes: std::slice::Iter<'a, (usize, usize, i32)>,
+}
+
+impl<'a, K> Iterator for PathIter<'a, K> {
+    type Item = Token<'a, K>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let &(x, y, cost) = self.nodes.next()?;
+        let node = &self.analyzed.column(y)[x];
+        Some(self.analyzed.token(node, y, cost))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analyze, Features, Info, Matrix, PrefixMapInsert, Token, Trie, Unknown};
+
+    fn words<'a, K: Copy + 'a>(iter: impl Iterator<Item = Token<'a, K>>) -> Vec<Vec<K>> {
+        iter.map(|token| token.word.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_nbest_order() {
+        let mut dic = Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("b", Info::new(1, 1, 10));
+        dic.insert("ab", Info::new(1, 1, 15));
+        dic.insert("ab", Info::new(1, 1, 30));
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0; 4],
+        };
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze::<u8, _>("ab", &dic, &matrix, &unknown, &features).unwrap();
+        let paths: Vec<_> = analyzed.nbest(&matrix).collect();
+        let costs: Vec<_> = paths.iter().map(|p| p.cost).collect();
+        assert_eq!(costs, vec![15, 20, 30]);
+        assert_eq!(paths[0].cost, analyzed.cost);
+        assert_eq!(words(paths[0].iter()), words(analyzed.iter()));
+        assert_eq!(words(paths[1].iter()), vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_nbest_token_cost() {
+        let mut dic = Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("b", Info::new(1, 1, 10));
+        dic.insert("ab", Info::new(1, 1, 15));
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0, 1, 2, 3],
+        };
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let analyzed = analyze::<u8, _>("ab", &dic, &matrix, &unknown, &features).unwrap();
+        let paths: Vec<_> = analyzed.nbest(&matrix).collect();
+        assert_eq!(paths.len(), 2);
+        for path in &paths {
+            assert_eq!(path.iter().next().unwrap().cost + 1, path.cost);
+        }
+        let costs: Vec<_> = paths[1].iter().map(|t| t.cost).collect();
+        assert_eq!(costs, vec![10 + 3 + 10 + 2, 10 + 2]);
+    }
+}
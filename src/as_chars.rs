@@ -1,3 +1,4 @@
+use std::char::{self as chars, REPLACEMENT_CHARACTER};
 use std::iter::Cloned;
 use std::iter::FromIterator;
 use std::slice::Iter;
@@ -73,6 +74,50 @@ impl IntoString for char {
 
 // -----------------------------------------------------------------------------
 
+/// キーの単位 (UTF-8 のバイト、UTF-16 の符号単位、文字) から文字を組み立てる。
+pub trait CodeUnit: Copy {
+    /// `units` で一文字になればその文字を返す。続きの単位が必要なら `None` を返す。
+    /// 不正な並びは U+FFFD として扱う。
+    fn decode(units: &[Self]) -> Option<char>;
+}
+
+impl CodeUnit for u8 {
+    #[inline]
+    fn decode(units: &[u8]) -> Option<char> {
+        let len = match units[0] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        if units.len() < len {
+            return None;
+        }
+        let ch = str::from_utf8(units).ok().and_then(|s| s.chars().next());
+        Some(ch.unwrap_or(REPLACEMENT_CHARACTER))
+    }
+}
+
+impl CodeUnit for u16 {
+    #[inline]
+    fn decode(units: &[u16]) -> Option<char> {
+        if units.len() == 1 && (0xD800..0xDC00).contains(&units[0]) {
+            return None;
+        }
+        let ch = chars::decode_utf16(units.iter().cloned()).next()?;
+        Some(ch.unwrap_or(REPLACEMENT_CHARACTER))
+    }
+}
+
+impl CodeUnit for char {
+    #[inline]
+    fn decode(units: &[char]) -> Option<char> {
+        Some(units[0])
+    }
+}
+
+// -----------------------------------------------------------------------------
+
 pub trait AsUsize: Copy {
     const MAX: usize;
 
@@ -1,65 +1,286 @@
+#[path = "common/mod.rs"]
+mod common;
+
 use dotamoji;
 
 use std::env;
-use std::io::{self, BufRead};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::time::Instant;
 
+use common::or_exit;
 use dotamoji::*;
 
+/// 辞書とコスト行列のファイル、またはそれらをまとめたファイル
+enum Input {
+    Files { dic_file: String, mat_file: String },
+    Bundle(String),
+}
+
 #[inline]
-fn analyze<K, D>(dic_file: &str, mat_file: &str)
+fn analyze<K, D>(input: &Input, mut opts: Options)
 where
     for<'a> &'a str: AsChars<K>,
-    K: Copy + IntoString,
+    K: Copy + Ord + IntoString,
     D: LoadDict<K, Info>,
 {
-    let start = Instant::now();
-    let dic = D::load_from_file(dic_file);
-    eprintln!("load_dic: {:?}", start.elapsed());
+    let (dic, mat, unknown, mut features) = match input {
+        Input::Bundle(file) => {
+            let start = Instant::now();
+            let tokenizer = or_exit(Tokenizer::<K, D>::load_from_file(file));
+            eprintln!("load_bundle: {:?}", start.elapsed());
+            let metadata = tokenizer.metadata();
+            eprintln!(
+                "bundle: {} {} ({})",
+                metadata.source, metadata.version, metadata.build_date
+            );
+            let (dic, mat, unknown, features, _) = tokenizer.into_parts();
+            (dic, mat, unknown, features)
+        }
+        Input::Files { dic_file, mat_file } => {
+            let start = Instant::now();
+            let dic = or_exit(D::load_from_file(dic_file));
+            eprintln!("load_dic: {:?}", start.elapsed());
 
+            let start = Instant::now();
+            let mat = or_exit(Matrix::load_from_file(mat_file));
+            eprintln!("load_mat: {:?}", start.elapsed());
+            or_exit(mat.check_dict(&dic).map_err(|e| e.in_file(dic_file)));
+            let unknown = opts.unknown.take().unwrap_or_default();
+            or_exit(unknown.check_matrix(&mat));
+
+            let features = match &opts.feature_file {
+                Some(file) => or_exit(Features::load_from_file(file)),
+                None => Features::new(),
+            };
+            (dic, mat, unknown, features)
+        }
+    };
+
+    if opts.user_dicts.is_empty() {
+        run(&dic, &mat, &unknown, &features, &opts);
+        return;
+    }
     let start = Instant::now();
-    let mat = Matrix::load_from_file(mat_file);
-    eprintln!("load_mat: {:?}", start.elapsed());
+    let mut layered = LayeredDict::new(dic);
+    for (file, cost_offset) in &opts.user_dicts {
+        // ヘッダのあるファイルは構築済みの辞書、それ以外は辞書テキストとして読み込む
+        if DictHeader::load_from_file(file).is_ok() {
+            let user = or_exit(D::load_from_file(file));
+            or_exit(mat.check_dict(&user).map_err(|e| e.in_file(file)));
+            layered.add_layer(&user, *cost_offset);
+        } else {
+            let mut user = Trie::<K, Info>::new();
+            let reader = BufReader::new(or_exit(File::open(file).map_err(Error::from)));
+            let result =
+                SourceReader::new(reader).insert_into(&mut user, Some(&mut features), Some(&mat));
+            or_exit(result.map_err(|e| e.in_file(file)));
+            layered.add_layer(&user, *cost_offset);
+        }
+    }
+    eprintln!("load_user_dic: {:?}", start.elapsed());
+    run(&layered, &mat, &unknown, &features, &opts);
+}
 
+fn run<K, D>(dic: &D, mat: &Matrix, unknown: &Unknown, features: &Features, opts: &Options)
+where
+    for<'a> &'a str: AsChars<K>,
+    K: Copy + IntoString,
+    D: PrefixMap<K, Info>,
+{
     let stdin = io::stdin();
     for line in stdin.lock().lines().filter_map(Result::ok) {
         let start = Instant::now();
-        let result = dotamoji::analyze(line.trim(), &dic, &mat);
+        let result = dotamoji::analyze(line.trim(), dic, mat, unknown, features);
 
-        if let Ok(analyzed) = result {
-            eprintln!("analyze: {:?}", start.elapsed());
+        match result {
+            Ok(analyzed) => {
+                eprintln!("analyze: {:?}", start.elapsed());
 
-            println!("cost = {}", analyzed.cost);
-            for token in analyzed.iter() {
-                let word = IntoString::into_string(token.word);
-                println!("id:{:>5} | cost:{:>6} | {}", token.id, token.cost, word);
+                if let Some(format) = &opts.lattice {
+                    let lattice = analyzed.lattice(mat);
+                    let stdout = io::stdout();
+                    let result = match format.as_str() {
+                        "dot" => lattice.write_dot(stdout.lock()),
+                        _ => lattice.write_json(stdout.lock()),
+                    };
+                    result.expect("ラティスを出力できません。");
+                    continue;
+                } else if let Some(temperature) = opts.marginal {
+                    print_marginals(&analyzed, mat, temperature);
+                } else if opts.nbest > 0 {
+                    for path in analyzed.nbest(mat).take(opts.nbest) {
+                        println!("cost = {}", path.cost);
+                        print_tokens(path.iter());
+                    }
+                } else {
+                    println!("cost = {}", analyzed.cost);
+                    print_tokens(analyzed.iter());
+                }
             }
-        } else {
-            println!("形態素解析に失敗しました。");
+            Err(e) => println!("{}", e),
         }
         println!();
     }
 }
 
+fn print_tokens<'a, K, I>(tokens: I)
+where
+    K: IntoString + 'a,
+    I: Iterator<Item = Token<'a, K>>,
+{
+    for token in tokens {
+        let word = IntoString::into_string(token.word);
+        if let Some(feature) = token.feature {
+            println!(
+                "id:{:>5} | cost:{:>6} | {}\t{}",
+                token.id, token.cost, word, feature.raw
+            );
+        } else {
+            println!("id:{:>5} | cost:{:>6} | {}", token.id, token.cost, word);
+        }
+    }
+}
+
+fn print_marginals<K: IntoString>(analyzed: &Analyzed<'_, K>, mat: &Matrix, temperature: f64) {
+    let marginals = or_exit(analyzed.marginals(mat, temperature));
+    println!("log_z = {}", marginals.log_z);
+    for marginal in marginals.iter() {
+        let token = marginal.token;
+        let word = IntoString::into_string(token.word);
+        println!(
+            "pos:{:>4} | id:{:>5} | prob:{:.6} | {}",
+            marginal.start, token.id, marginal.probability, word
+        );
+    }
+}
+
+struct Options {
+    unknown: Option<Unknown>,
+    feature_file: Option<String>,
+    nbest: usize,
+    marginal: Option<f64>,
+    lattice: Option<String>,
+    /// ユーザー辞書のファイルと、コストに加える値
+    user_dicts: Vec<(String, i16)>,
+}
+
 fn main() {
-    let mut args = env::args();
-    let _ = args.next().expect("実行ファイルが不明！？");
-    let dictype = args.next().expect("タイプが指定されていません。");
-    let dic_file = args.next().expect("辞書ファイルが指定されていません。");
-    let mat_file = args
-        .next()
-        .expect("コスト行列ファイルが指定されていません。");
+    let mut nbest = 0;
+    let mut marginal = None;
+    let mut lattice = None;
+    let mut feature_file = None;
+    let mut char_def = None;
+    let mut unk_def = None;
+    let mut user_dicts: Vec<(String, i16)> = vec![];
+    let mut bundle = None;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-N" | "--nbest" => {
+                let n = args.next().expect("-N の値が指定されていません。");
+                nbest = n.parse().expect("-N の値が数値ではありません。");
+            }
+            "--marginal" => {
+                let t = args
+                    .next()
+                    .expect("--marginal の温度が指定されていません。");
+                marginal = Some(t.parse().expect("--marginal の温度が数値ではありません。"));
+            }
+            "--lattice" => {
+                let format = args.next().expect("--lattice の形式が指定されていません。");
+                match format.as_str() {
+                    "dot" | "json" => lattice = Some(format),
+                    _ => panic!("--lattice の形式は dot か json を指定してください。"),
+                }
+            }
+            "--feature" => {
+                feature_file = Some(args.next().expect("素性ファイルが指定されていません。"));
+            }
+            "--char-def" => {
+                char_def = Some(args.next().expect("char.def が指定されていません。"));
+            }
+            "--unk-def" => {
+                unk_def = Some(args.next().expect("unk.def が指定されていません。"));
+            }
+            "--bundle" => {
+                bundle = Some(
+                    args.next()
+                        .expect("辞書をまとめたファイルが指定されていません。"),
+                );
+            }
+            "--user-dict" => {
+                let file = args.next().expect("ユーザー辞書が指定されていません。");
+                user_dicts.push((file, 0));
+            }
+            "--user-cost" => {
+                let cost = args.next().expect("--user-cost の値が指定されていません。");
+                let cost = cost
+                    .parse()
+                    .expect("--user-cost の値が数値ではありません。");
+                match user_dicts.last_mut() {
+                    Some((_, cost_offset)) => *cost_offset = cost,
+                    None => panic!("--user-cost は --user-dict の後に指定してください。"),
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // タイプを省略した場合は辞書ファイルのヘッダから判別する
+    let mut args = positional.into_iter();
+    let (input, dictype) = match bundle {
+        Some(file) => {
+            if char_def.is_some() || unk_def.is_some() || feature_file.is_some() {
+                panic!("--bundle と --char-def、--unk-def、--feature は同時に指定できません。");
+            }
+            let dictype = args.next().unwrap_or_else(|| {
+                let header = or_exit(BundleHeader::load_from_file(&file));
+                header.dictionary.type_name()
+            });
+            (Input::Bundle(file), dictype)
+        }
+        None => {
+            let dictype = if args.len() > 2 { args.next() } else { None };
+            let dic_file = args.next().expect("辞書ファイルが指定されていません。");
+            let mat_file = args
+                .next()
+                .expect("コスト行列ファイルが指定されていません。");
+            let dictype = dictype
+                .unwrap_or_else(|| or_exit(DictHeader::load_from_file(&dic_file)).type_name());
+            (Input::Files { dic_file, mat_file }, dictype)
+        }
+    };
+    let unknown = match (char_def, unk_def) {
+        (Some(char_def), Some(unk_def)) => {
+            Some(or_exit(Unknown::load_from_file(&char_def, &unk_def)))
+        }
+        (None, None) => None,
+        _ => panic!("--char-def と --unk-def は両方指定してください。"),
+    };
+    let opts = Options {
+        unknown,
+        feature_file,
+        nbest,
+        marginal,
+        lattice,
+        user_dicts,
+    };
 
     match dictype.as_str() {
-        "array8" => analyze::<u8, DoubleArray<u8, Info>>(&dic_file, &mat_file),
-        "array16" => analyze::<u16, DoubleArray<u16, Info>>(&dic_file, &mat_file),
-        "array32" => analyze::<char, DoubleArray<char, Info>>(&dic_file, &mat_file),
-        "hash8" => analyze::<u8, RecursiveHashMap<u8, Info>>(&dic_file, &mat_file),
-        "hash16" => analyze::<u16, RecursiveHashMap<u16, Info>>(&dic_file, &mat_file),
-        "hash32" => analyze::<char, RecursiveHashMap<char, Info>>(&dic_file, &mat_file),
-        "trie8" => analyze::<u8, Trie<u8, Info>>(&dic_file, &mat_file),
-        "trie16" => analyze::<u16, Trie<u16, Info>>(&dic_file, &mat_file),
-        "trie32" => analyze::<char, Trie<char, Info>>(&dic_file, &mat_file),
+        "array8" => analyze::<u8, DoubleArray<u8, Info>>(&input, opts),
+        "array16" => analyze::<u16, DoubleArray<u16, Info>>(&input, opts),
+        "array32" => analyze::<char, DoubleArray<char, Info>>(&input, opts),
+        "hash8" => analyze::<u8, RecursiveHashMap<u8, Info>>(&input, opts),
+        "hash16" => analyze::<u16, RecursiveHashMap<u16, Info>>(&input, opts),
+        "hash32" => analyze::<char, RecursiveHashMap<char, Info>>(&input, opts),
+        "trie8" => analyze::<u8, Trie<u8, Info>>(&input, opts),
+        "trie16" => analyze::<u16, Trie<u16, Info>>(&input, opts),
+        "trie32" => analyze::<char, Trie<char, Info>>(&input, opts),
+        "mapped8" => analyze::<u8, MappedDoubleArray<u8>>(&input, opts),
+        "mapped16" => analyze::<u16, MappedDoubleArray<u16>>(&input, opts),
+        "mapped32" => analyze::<char, MappedDoubleArray<char>>(&input, opts),
         _ => panic!("不明なタイプです。"),
     }
 }
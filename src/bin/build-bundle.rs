@@ -0,0 +1,123 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::or_exit;
+use dotamoji::*;
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[inline]
+fn bundle<K, D>(dic_file: &str, mat_file: &str, output: &str, opts: Options)
+where
+    D: LoadDict<K, Info> + SaveDict<K, Info>,
+{
+    let dic = or_exit(D::load_from_file(dic_file));
+    let matrix = or_exit(Matrix::load_from_file(mat_file));
+
+    let tokenizer = or_exit(Tokenizer::new(
+        dic,
+        matrix,
+        opts.unknown,
+        opts.features,
+        opts.metadata,
+    ));
+    let file = File::create(output).expect("ファイルを作成できません。");
+    let tokenizer = or_exit(tokenizer.save_to_file(BufWriter::new(file)));
+    let metadata = tokenizer.metadata();
+    println!(
+        "{} を作成しました。({} {}, {})",
+        output, metadata.source, metadata.version, metadata.build_date
+    );
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map_or_else(
+        || path.to_owned(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+struct Options {
+    unknown: Unknown,
+    features: Features,
+    metadata: Metadata,
+}
+
+fn main() {
+    let mut feature_file = None;
+    let mut char_def = None;
+    let mut unk_def = None;
+    let mut source = None;
+    let mut version = None;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--feature" => {
+                feature_file = Some(args.next().expect("素性ファイルが指定されていません。"));
+            }
+            "--char-def" => {
+                char_def = Some(args.next().expect("char.def が指定されていません。"));
+            }
+            "--unk-def" => {
+                unk_def = Some(args.next().expect("unk.def が指定されていません。"));
+            }
+            "--source" => {
+                source = Some(args.next().expect("辞書の名前が指定されていません。"));
+            }
+            "--version" => {
+                version = Some(args.next().expect("バージョンが指定されていません。"));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // タイプを省略した場合は辞書ファイルのヘッダから判別する
+    let mut args = positional.into_iter();
+    let dictype = if args.len() > 3 { args.next() } else { None };
+    let dic_file = args.next().expect("辞書ファイルが指定されていません。");
+    let mat_file = args
+        .next()
+        .expect("コスト行列ファイルが指定されていません。");
+    let output = args.next().expect("出力ファイルが指定されていません。");
+    let dictype =
+        dictype.unwrap_or_else(|| or_exit(DictHeader::load_from_file(&dic_file)).type_name());
+
+    let unknown = match (char_def, unk_def) {
+        (Some(char_def), Some(unk_def)) => or_exit(Unknown::load_from_file(&char_def, &unk_def)),
+        (None, None) => Unknown::default(),
+        _ => panic!("--char-def と --unk-def は両方指定してください。"),
+    };
+    let features = match feature_file {
+        Some(file) => or_exit(Features::load_from_file(&file)),
+        None => Features::new(),
+    };
+    let opts = Options {
+        unknown,
+        features,
+        // 名前を省略した場合は辞書ファイル名を使う
+        metadata: Metadata::new(
+            source.unwrap_or_else(|| file_name(&dic_file)),
+            version.unwrap_or_else(|| "-".to_owned()),
+        ),
+    };
+
+    let (dic, mat, out) = (&dic_file, &mat_file, &output);
+    match dictype.as_str() {
+        "array8" => bundle::<u8, DoubleArray<u8, Info>>(dic, mat, out, opts),
+        "array16" => bundle::<u16, DoubleArray<u16, Info>>(dic, mat, out, opts),
+        "array32" => bundle::<char, DoubleArray<char, Info>>(dic, mat, out, opts),
+        "hash8" => bundle::<u8, RecursiveHashMap<u8, Info>>(dic, mat, out, opts),
+        "hash16" => bundle::<u16, RecursiveHashMap<u16, Info>>(dic, mat, out, opts),
+        "hash32" => bundle::<char, RecursiveHashMap<char, Info>>(dic, mat, out, opts),
+        "trie8" => bundle::<u8, Trie<u8, Info>>(dic, mat, out, opts),
+        "trie16" => bundle::<u16, Trie<u16, Info>>(dic, mat, out, opts),
+        "trie32" => bundle::<char, Trie<char, Info>>(dic, mat, out, opts),
+        "mapped8" => bundle::<u8, MappedDoubleArray<u8>>(dic, mat, out, opts),
+        "mapped16" => bundle::<u16, MappedDoubleArray<u16>>(dic, mat, out, opts),
+        "mapped32" => bundle::<char, MappedDoubleArray<char>>(dic, mat, out, opts),
+        _ => panic!("不明なタイプです。"),
+    }
+}
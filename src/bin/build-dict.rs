@@ -1,72 +1,121 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::or_exit;
 use dotamoji::*;
 use std::env;
 use std::fs::File;
+use std::io;
 use std::io::BufWriter;
-use std::io::{self, BufRead};
-use std::str::FromStr;
-
-#[inline]
-fn read_int<T: FromStr>(str: &str) -> T {
-    match str.parse() {
-        Ok(x) => x,
-        Err(_) => panic!("{:?}を数値に変換できません", str),
-    }
-}
 
 #[inline]
-fn line_to_info<'a>(line: &'a str) -> (&'a str, Info) {
-    let mut row = line.split(",");
-    let word = row.next().expect("文字が取得できません");
-    let left = read_int(row.next().expect("LeftIDが取得できません"));
-    let right = read_int(row.next().expect("RightIDが取得できません"));
-    let cost = read_int(row.next().expect("コストが取得できません"));
-    (word, Info::new(left, right, cost))
+fn build<K, D>(file_path: &str, opts: &Options)
+where
+    for<'a> &'a str: AsChars<K>,
+    D: SaveDict<K, Info> + PrefixMapInsert<K, Info> + Default,
+{
+    save(read::<K, D>(opts), file_path);
 }
 
-#[inline]
-fn build<K, D>(file_path: &str)
+/// 標準入力の単語を `D` に登録する。
+fn read<K, D>(opts: &Options) -> D
 where
     for<'a> &'a str: AsChars<K>,
-    D: SaveDict<K, Info> + Default,
+    D: PrefixMapInsert<K, Info> + Default,
 {
     let stdin = io::stdin();
     let mut dic = D::default();
-    for line in stdin.lock().lines().filter_map(Result::ok) {
-        let (word, info) = line_to_info(&line);
-        dic.insert(word, info);
+    let mut features = opts.feature_file.as_ref().map(|_| Features::new());
+    let reader = SourceReader::with_charset(stdin.lock(), opts.charset).with_format(opts.format);
+    or_exit(reader.insert_into(&mut dic, features.as_mut(), opts.matrix.as_ref()));
+    if let (Some(path), Some(features)) = (&opts.feature_file, features) {
+        let file = File::create(path).expect("ファイルを作成できません。");
+        or_exit(features.save_to_file(BufWriter::new(file)));
+        eprintln!("{} を作成しました。", path);
     }
+    dic
+}
+
+fn save<K, D: SaveDict<K, Info>>(dic: D, file_path: &str) {
     if file_path == "-" {
         let stdout = io::stdout();
         let handle = stdout.lock();
-        dic.save_to_file(handle);
+        or_exit(dic.save_to_file(handle));
     } else {
         let file = File::create(file_path).expect("ファイルを作成できません。");
         let file = BufWriter::new(file);
-        dic.save_to_file(file);
+        or_exit(dic.save_to_file(file));
         println!("{} を作成しました。", file_path);
     }
 }
 
+struct Options {
+    feature_file: Option<String>,
+    matrix: Option<Matrix>,
+    charset: Charset,
+    format: SourceFormat,
+}
+
 fn main() {
-    let mut args = env::args();
-    let _ = args.next().expect("実行ファイルが不明！？");
+    let mut feature_file = None;
+    let mut matrix_file = None;
+    let mut charset = Charset::Utf8;
+    let mut format = SourceFormat::Ipadic;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--feature" => {
+                feature_file = Some(args.next().expect("素性ファイルが指定されていません。"));
+            }
+            "--matrix" => {
+                let file = args
+                    .next()
+                    .expect("コスト行列ファイルが指定されていません。");
+                matrix_file = Some(file);
+            }
+            "--charset" => {
+                let name = args.next().expect("文字コードが指定されていません。");
+                charset = or_exit(name.parse());
+            }
+            "--format" => {
+                let name = args.next().expect("辞書の形式が指定されていません。");
+                format = or_exit(name.parse());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut args = positional.into_iter();
     let dictype = args.next().expect("タイプが指定されていません。");
     let file = args.next().expect("ファイルが指定されていません。");
+    let matrix =
+        matrix_file.map(|file| or_exit(Matrix::load_from_file_with_charset(&file, charset)));
+    let opts = Options {
+        feature_file,
+        matrix,
+        charset,
+        format,
+    };
 
     match dictype.as_str() {
-        "array8" => build::<u8, DoubleArray<u8, Info>>(&file),
-        "array16" => build::<u16, DoubleArray<u16, Info>>(&file),
-        "hash8" => build::<u8, RecursiveHashMap<u8, Info>>(&file),
-        "hash16" => build::<u16, RecursiveHashMap<u16, Info>>(&file),
-        "hash32" => build::<char, RecursiveHashMap<char, Info>>(&file),
-        "trie8" => build::<u8, Trie<u8, Info>>(&file),
-        "trie16" => build::<u16, Trie<u16, Info>>(&file),
-        "trie32" => build::<char, Trie<char, Info>>(&file),
-        "trans8" => build::<u8, Trie2DAMap<u8, Info>>(&file),
-        "trans16" => build::<u16, Trie2DAMap<u16, Info>>(&file),
-        "trans32" => build::<char, Trie2DAMap<char, Info>>(&file),
-        "fast8" => build::<u8, FastBuildDoubleArray<u8, Info>>(&file),
-        "fast16" => build::<u16, FastBuildDoubleArray<u16, Info>>(&file),
+        "array8" => build::<u8, DoubleArray<u8, Info>>(&file, &opts),
+        "array16" => build::<u16, DoubleArray<u16, Info>>(&file, &opts),
+        "hash8" => build::<u8, RecursiveHashMap<u8, Info>>(&file, &opts),
+        "hash16" => build::<u16, RecursiveHashMap<u16, Info>>(&file, &opts),
+        "hash32" => build::<char, RecursiveHashMap<char, Info>>(&file, &opts),
+        "trie8" => build::<u8, Trie<u8, Info>>(&file, &opts),
+        "trie16" => build::<u16, Trie<u16, Info>>(&file, &opts),
+        "trie32" => build::<char, Trie<char, Info>>(&file, &opts),
+        "trans8" => build::<u8, Trie2DAMap<u8, Info>>(&file, &opts),
+        "trans16" => build::<u16, Trie2DAMap<u16, Info>>(&file, &opts),
+        "trans32" => build::<char, Trie2DAMap<char, Info>>(&file, &opts),
+        "fast8" => build::<u8, FastBuildDoubleArray<u8, Info>>(&file, &opts),
+        "fast16" => build::<u16, FastBuildDoubleArray<u16, Info>>(&file, &opts),
+        // メモリマップ形式は読み込み専用なので、トライ木に登録してから変換する
+        "mapped8" => save::<u8, _>(Trie2MappedMap::from(read::<u8, Trie<_, _>>(&opts)), &file),
+        "mapped16" => save::<u16, _>(Trie2MappedMap::from(read::<u16, Trie<_, _>>(&opts)), &file),
+        "mapped32" => save::<char, _>(Trie2MappedMap::from(read::<char, Trie<_, _>>(&opts)), &file),
         _ => panic!("不明なタイプです。"),
     }
 }
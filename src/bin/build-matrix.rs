@@ -0,0 +1,35 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+
+use common::or_exit;
+use dotamoji::*;
+
+fn main() {
+    let mut charset = Charset::Utf8;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--charset" => {
+                let name = args.next().expect("文字コードが指定されていません。");
+                charset = or_exit(name.parse());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut args = positional.into_iter();
+    let input = args
+        .next()
+        .expect("コスト行列ファイルが指定されていません。");
+    let output = args.next().expect("出力ファイルが指定されていません。");
+
+    let matrix = or_exit(Matrix::load_from_file_with_charset(&input, charset));
+    let file = File::create(&output).expect("ファイルを作成できません。");
+    or_exit(matrix.save_binary(BufWriter::new(file)));
+    println!("{} を作成しました。", output);
+}
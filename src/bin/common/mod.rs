@@ -0,0 +1,14 @@
+// 各コマンドで共有する関数。`src/bin` の直下に置くとコマンドとして扱われるため、ここに置く。
+use dotamoji::Error;
+use std::process;
+
+/// エラーを表示して終了する
+pub fn or_exit<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
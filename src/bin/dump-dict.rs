@@ -0,0 +1,77 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::or_exit;
+use dotamoji::*;
+use std::env;
+use std::io::{self, BufWriter, Write};
+
+#[inline]
+fn dump<K, D>(file: &str, features: Option<&Features>)
+where
+    K: IntoString,
+    D: LoadDict<K, Info>,
+{
+    let dic = or_exit(D::load_from_file(file));
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut cnt = 0;
+    dic.each_entry(|key, infos| {
+        let surface = IntoString::into_string(key);
+        for info in infos {
+            let feature = features
+                .and_then(|features| features.get(info.feature))
+                .map(|feature| feature.raw.to_owned());
+            let entry = Entry {
+                surface: surface.clone(),
+                left_id: info.left_id,
+                right_id: info.right_id,
+                cost: info.cost,
+                feature,
+            };
+            writeln!(out, "{}", entry).expect("出力できません。");
+            cnt += 1;
+        }
+    });
+    out.flush().expect("出力できません。");
+    eprintln!("{} 件のデータを出力しました。", cnt);
+}
+
+fn main() {
+    let mut feature_file = None;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--feature" => {
+                feature_file = Some(args.next().expect("素性ファイルが指定されていません。"));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // タイプを省略した場合は辞書ファイルのヘッダから判別する
+    let mut args = positional.into_iter();
+    let dictype = if args.len() > 1 { args.next() } else { None };
+    let file = args.next().expect("ファイルが指定されていません。");
+    let dictype = dictype.unwrap_or_else(|| or_exit(DictHeader::load_from_file(&file)).type_name());
+    let features = feature_file.map(|file| or_exit(Features::load_from_file(&file)));
+    let features = features.as_ref();
+
+    match dictype.as_str() {
+        "array8" => dump::<u8, DoubleArray<u8, Info>>(&file, features),
+        "array16" => dump::<u16, DoubleArray<u16, Info>>(&file, features),
+        "array32" => dump::<char, DoubleArray<char, Info>>(&file, features),
+        "hash8" => dump::<u8, RecursiveHashMap<u8, Info>>(&file, features),
+        "hash16" => dump::<u16, RecursiveHashMap<u16, Info>>(&file, features),
+        "hash32" => dump::<char, RecursiveHashMap<char, Info>>(&file, features),
+        "trie8" => dump::<u8, Trie<u8, Info>>(&file, features),
+        "trie16" => dump::<u16, Trie<u16, Info>>(&file, features),
+        "trie32" => dump::<char, Trie<char, Info>>(&file, features),
+        "mapped8" => dump::<u8, MappedDoubleArray<u8>>(&file, features),
+        "mapped16" => dump::<u16, MappedDoubleArray<u16>>(&file, features),
+        "mapped32" => dump::<char, MappedDoubleArray<char>>(&file, features),
+        _ => panic!("不明なタイプです。"),
+    }
+}
@@ -1,45 +1,91 @@
+#[path = "common/mod.rs"]
+mod common;
+
 use std::env;
-use std::io::{self, BufRead};
+use std::io;
 
+use common::or_exit;
 use dotamoji::*;
 
 #[inline]
-fn test_all<K, D>(file: &str)
+fn test_all<K, D>(file: &str, matrix: Option<&Matrix>, charset: Charset, format: SourceFormat)
 where
     for<'a> &'a str: AsChars<K>,
     D: LoadDict<K, Info>,
 {
-    let dic = D::load_from_file(file);
+    let dic = or_exit(D::load_from_file(file));
 
     let stdin = io::stdin();
     let mut cnt = 0;
-    for (ix, line) in stdin.lock().lines().filter_map(Result::ok).enumerate() {
-        let word = line.split(",").next().unwrap();
-        if let Some(_) = dic.get(word) {
+    let mut reader = SourceReader::with_charset(stdin.lock(), charset).with_format(format);
+    while let Some(entry) = reader.next() {
+        let word = or_exit(entry).surface;
+        if let Some(infos) = dic.get(word.as_str()) {
+            if let Some(matrix) = matrix {
+                for info in infos {
+                    or_exit(
+                        matrix
+                            .check_info(info)
+                            .map_err(|e| e.at_line(reader.line())),
+                    );
+                }
+            }
             cnt += 1;
         } else {
-            panic!("{} が見つかりません。({}行目)", word, ix + 1);
+            panic!("{} が見つかりません。({}行目)", word, reader.line());
         }
     }
     println!("{} 件のデータすべてが存在しました。", cnt);
 }
 
 fn main() {
-    let mut args = env::args();
-    let _ = args.next().expect("実行ファイルが不明！？");
-    let dictype = args.next().expect("タイプが指定されていません。");
+    let mut matrix_file = None;
+    let mut charset = Charset::Utf8;
+    let mut format = SourceFormat::Ipadic;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--matrix" => {
+                let file = args
+                    .next()
+                    .expect("コスト行列ファイルが指定されていません。");
+                matrix_file = Some(file);
+            }
+            "--charset" => {
+                let name = args.next().expect("文字コードが指定されていません。");
+                charset = or_exit(name.parse());
+            }
+            "--format" => {
+                let name = args.next().expect("辞書の形式が指定されていません。");
+                format = or_exit(name.parse());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // タイプを省略した場合は辞書ファイルのヘッダから判別する
+    let mut args = positional.into_iter();
+    let dictype = if args.len() > 1 { args.next() } else { None };
     let file = args.next().expect("ファイルが指定されていません。");
+    let dictype = dictype.unwrap_or_else(|| or_exit(DictHeader::load_from_file(&file)).type_name());
+    let matrix =
+        matrix_file.map(|file| or_exit(Matrix::load_from_file_with_charset(&file, charset)));
+    let matrix = matrix.as_ref();
 
     match dictype.as_str() {
-        "array8" => test_all::<u8, DoubleArray<u8, Info>>(&file),
-        "array16" => test_all::<u16, DoubleArray<u16, Info>>(&file),
-        "array32" => test_all::<char, DoubleArray<char, Info>>(&file),
-        "hash8" => test_all::<u8, RecursiveHashMap<u8, Info>>(&file),
-        "hash16" => test_all::<u16, RecursiveHashMap<u16, Info>>(&file),
-        "hash32" => test_all::<char, RecursiveHashMap<char, Info>>(&file),
-        "trie8" => test_all::<u8, Trie<u8, Info>>(&file),
-        "trie16" => test_all::<u16, Trie<u16, Info>>(&file),
-        "trie32" => test_all::<char, Trie<char, Info>>(&file),
+        "array8" => test_all::<u8, DoubleArray<u8, Info>>(&file, matrix, charset, format),
+        "array16" => test_all::<u16, DoubleArray<u16, Info>>(&file, matrix, charset, format),
+        "array32" => test_all::<char, DoubleArray<char, Info>>(&file, matrix, charset, format),
+        "hash8" => test_all::<u8, RecursiveHashMap<u8, Info>>(&file, matrix, charset, format),
+        "hash16" => test_all::<u16, RecursiveHashMap<u16, Info>>(&file, matrix, charset, format),
+        "hash32" => test_all::<char, RecursiveHashMap<char, Info>>(&file, matrix, charset, format),
+        "trie8" => test_all::<u8, Trie<u8, Info>>(&file, matrix, charset, format),
+        "trie16" => test_all::<u16, Trie<u16, Info>>(&file, matrix, charset, format),
+        "trie32" => test_all::<char, Trie<char, Info>>(&file, matrix, charset, format),
+        "mapped8" => test_all::<u8, MappedDoubleArray<u8>>(&file, matrix, charset, format),
+        "mapped16" => test_all::<u16, MappedDoubleArray<u16>>(&file, matrix, charset, format),
+        "mapped32" => test_all::<char, MappedDoubleArray<char>>(&file, matrix, charset, format),
         _ => panic!("不明なタイプです。"),
     }
 }
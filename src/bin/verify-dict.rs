@@ -0,0 +1,43 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::or_exit;
+use dotamoji::*;
+use std::env;
+use std::process;
+
+/// 表示する問題の最大数
+const MAX_DEFECTS: usize = 100;
+
+/// 検査の結果を表示し、問題があれば終了する
+fn report(file: &str, defects: Vec<Defect>) {
+    if defects.is_empty() {
+        println!("{} に問題はありませんでした。", file);
+        return;
+    }
+    for defect in defects.iter().take(MAX_DEFECTS) {
+        println!("{}", defect);
+    }
+    if defects.len() > MAX_DEFECTS {
+        println!("...");
+    }
+    println!("{} 件の問題が見つかりました。", defects.len());
+    process::exit(1);
+}
+
+fn main() {
+    // タイプを省略した場合は辞書ファイルのヘッダから判別する
+    let mut args = env::args().skip(1);
+    let dictype = if args.len() > 1 { args.next() } else { None };
+    let file = args.next().expect("ファイルが指定されていません。");
+    let dictype = dictype.unwrap_or_else(|| or_exit(DictHeader::load_from_file(&file)).type_name());
+
+    // 読み込むときにチェックサムを確かめ、その後で構造を検査する
+    let defects = match dictype.as_str() {
+        "array8" => or_exit(DoubleArray::<u8, Info>::load_from_file(&file)).verify(),
+        "array16" => or_exit(DoubleArray::<u16, Info>::load_from_file(&file)).verify(),
+        "array32" => or_exit(DoubleArray::<char, Info>::load_from_file(&file)).verify(),
+        _ => panic!("検査できるのはダブル配列 (array8、array16、array32) の辞書だけです。"),
+    };
+    report(&file, defects);
+}
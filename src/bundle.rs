@@ -0,0 +1,402 @@
+use crate::analyze::{analyze, Analyzed};
+use crate::as_chars::AsChars;
+use crate::dictionary::{self, checksum, DictHeader, LoadDict, SaveDict};
+use crate::error::{Error, Result};
+use crate::feature::Features;
+use crate::info::Info;
+use crate::matrix::Matrix;
+use crate::prefix_map::PrefixMap;
+use crate::unknown::Unknown;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ファイルの形式。数値はすべてリトルエンディアン。
+//
+// | 内容                                                          |
+// |---------------------------------------------------------------|
+// | 識別子 `DMJB` (4 バイト)                                      |
+// | バージョン (u32)                                              |
+// | これより後ろ全体の FNV-1a (u64)                               |
+// | メタデータ (`dictionary::write_section` の形式)               |
+// | 辞書 (`SaveDict::save_to_file` の出力)                        |
+// | コスト行列 (`Matrix::save_binary` の出力)                     |
+// | 未知語の定義 (bincode)                                        |
+// | 素性 (bincode)                                                |
+//
+// メタデータ以降の各部分は、前にバイト数 (u64) を付けて並べる。
+
+const MAGIC: &[u8; 4] = b"DMJB";
+const VERSION: u32 = 1;
+
+/// 辞書の出所などの情報
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// 元にした辞書の名前
+    pub source: String,
+    pub version: String,
+    /// 作成日 (UTC、`YYYY-MM-DD`)
+    pub build_date: String,
+}
+
+impl Metadata {
+    /// 作成日を今日にして作る。
+    pub fn new<S1: Into<String>, S2: Into<String>>(source: S1, version: S2) -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Metadata {
+            source: source.into(),
+            version: version.into(),
+            build_date: format_date(secs / 86400),
+        }
+    }
+}
+
+/// 1970-01-01 からの日数を `YYYY-MM-DD` にする。
+fn format_date(days: u64) -> String {
+    // グレゴリオ暦の 400 年周期で計算する
+    let days = days as i64 + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// 辞書、コスト行列、未知語の定義、素性をまとめたもの。
+///
+/// 一つのファイルに保存でき、読み込んだらそのまま形態素解析に使える。
+pub struct Tokenizer<K, D> {
+    dictionary: D,
+    matrix: Matrix,
+    unknown: Unknown,
+    features: Features,
+    metadata: Metadata,
+    phantom: PhantomData<K>,
+}
+
+impl<K, D: PrefixMap<K, Info>> Tokenizer<K, D> {
+    /// 辞書と未知語の定義の文脈IDがコスト行列に収まっていない場合はエラーになる。
+    pub fn new(
+        dictionary: D,
+        matrix: Matrix,
+        unknown: Unknown,
+        features: Features,
+        metadata: Metadata,
+    ) -> Result<Self> {
+        matrix.check_dict(&dictionary)?;
+        unknown.check_matrix(&matrix)?;
+        Ok(Tokenizer {
+            dictionary,
+            matrix,
+            unknown,
+            features,
+            metadata,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn analyze(&self, sentence: &str) -> Result<Analyzed<'_, K>>
+    where
+        for<'a> &'a str: AsChars<K>,
+        K: Copy,
+    {
+        analyze(
+            sentence,
+            &self.dictionary,
+            &self.matrix,
+            &self.unknown,
+            &self.features,
+        )
+    }
+
+    #[inline]
+    pub fn dictionary(&self) -> &D {
+        &self.dictionary
+    }
+
+    #[inline]
+    pub fn matrix(&self) -> &Matrix {
+        &self.matrix
+    }
+
+    #[inline]
+    pub fn unknown(&self) -> &Unknown {
+        &self.unknown
+    }
+
+    #[inline]
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// 辞書、コスト行列、未知語の定義、素性、メタデータに分ける。
+    #[inline]
+    pub fn into_parts(self) -> (D, Matrix, Unknown, Features, Metadata) {
+        (
+            self.dictionary,
+            self.matrix,
+            self.unknown,
+            self.features,
+            self.metadata,
+        )
+    }
+
+    /// 一つのファイルにまとめて保存する。
+    pub fn save_to_file<W: Write>(self, mut file: W) -> Result<Self>
+    where
+        D: SaveDict<K, Info>,
+    {
+        let mut body = vec![];
+        let mut section = vec![];
+        dictionary::write_section(&mut section, &self.metadata)?;
+        push_section(&mut body, &mut section);
+        let dictionary = self.dictionary.save_to_file(&mut section)?;
+        push_section(&mut body, &mut section);
+        self.matrix.save_binary(&mut section)?;
+        push_section(&mut body, &mut section);
+        bincode::serialize_into(&mut section, &self.unknown)?;
+        push_section(&mut body, &mut section);
+        self.features.save_to_file(&mut section)?;
+        push_section(&mut body, &mut section);
+
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&checksum(&body).to_le_bytes())?;
+        file.write_all(&body)?;
+        file.flush()?;
+        Ok(Tokenizer { dictionary, ..self })
+    }
+
+    /// `save_to_file` で保存したファイルを読み込む。チェックサムが一致しない場合はエラーになる。
+    ///
+    /// チェックサムを確かめるためにファイル全体をメモリに読み込み、辞書もそこから作る。
+    /// `MappedDoubleArray` をまとめた場合もメモリマップはせず、内容をコピーして保持する。
+    /// メモリマップで使う場合は、まとめずに `MappedDoubleArray::load_mapped` で読み込む。
+    pub fn load_from_file(file: &str) -> Result<Self>
+    where
+        D: LoadDict<K, Info>,
+    {
+        let load = || {
+            let mut bytes = vec![];
+            File::open(file)?.read_to_end(&mut bytes)?;
+            Tokenizer::from_bytes(&bytes)
+        };
+        load().map_err(|e: Error| e.in_file(file))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self>
+    where
+        D: LoadDict<K, Info>,
+    {
+        let mut body = read_header(bytes)?;
+        if bytes.len() < 16 || u64::from_le_bytes(copy8(&bytes[8..16])) != checksum(body) {
+            return Err(Error::format(
+                "チェックサムが一致しません。ファイルが壊れています",
+            ));
+        }
+        let metadata = dictionary::read_section(&mut next_section(&mut body)?, "メタデータ")?;
+        let dictionary = D::load_from_reader(next_section(&mut body)?)?;
+        let matrix = Matrix::from_binary_reader(next_section(&mut body)?)?;
+        let unknown = bincode::deserialize(next_section(&mut body)?)?;
+        let features = Features::from_reader(next_section(&mut body)?)?;
+        if !body.is_empty() {
+            return Err(Error::format("ファイルの末尾に余分なデータがあります"));
+        }
+        Tokenizer::new(dictionary, matrix, unknown, features, metadata)
+    }
+}
+
+/// まとめたファイルの先頭にある、メタデータと辞書の種類
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BundleHeader {
+    pub metadata: Metadata,
+    pub dictionary: DictHeader,
+}
+
+impl BundleHeader {
+    /// ファイル全体は読み込まず、先頭だけを読む。チェックサムは確かめない。
+    pub fn load_from_file(file: &str) -> Result<Self> {
+        let load = || {
+            let mut reader = BufReader::new(File::open(file)?);
+            let mut header = [0; 16];
+            reader.read_exact(&mut header).map_err(|_| not_bundle())?;
+            read_header(&header)?;
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            let metadata = dictionary::read_section(&mut reader, "メタデータ")?;
+            reader.read_exact(&mut len)?;
+            let dictionary = DictHeader::read(&mut reader)?;
+            Ok(BundleHeader {
+                metadata,
+                dictionary,
+            })
+        };
+        load().map_err(|e: Error| e.in_file(file))
+    }
+}
+
+#[inline]
+fn not_bundle() -> Error {
+    Error::format("辞書をまとめたファイルではありません")
+}
+
+/// 識別子とバージョンを確かめ、チェックサムより後ろを返す。
+fn read_header(bytes: &[u8]) -> Result<&[u8]> {
+    if bytes.len() < 16 || &bytes[..4] != MAGIC {
+        return Err(not_bundle());
+    }
+    let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if version != VERSION {
+        let message = format!("対応していないバージョンです ({})", version);
+        return Err(Error::format(message));
+    }
+    Ok(&bytes[16..])
+}
+
+#[inline]
+fn copy8(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0; 8];
+    array.copy_from_slice(&bytes[..8]);
+    array
+}
+
+/// `section` の前にバイト数を付けて `body` に移す。
+fn push_section(body: &mut Vec<u8>, section: &mut Vec<u8>) {
+    body.extend_from_slice(&(section.len() as u64).to_le_bytes());
+    body.append(section);
+}
+
+fn next_section<'a>(body: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let truncated = || Error::format("ファイルが途中で終わっています");
+    if body.len() < 8 {
+        return Err(truncated());
+    }
+    let len = u64::from_le_bytes(copy8(body));
+    if len > (body.len() - 8) as u64 {
+        return Err(truncated());
+    }
+    let (section, rest) = body[8..].split_at(len as usize);
+    *body = rest;
+    Ok(section)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefix_map::PrefixMapInsert;
+    use crate::{DoubleArray, MappedDoubleArray, Trie};
+
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "dotamoji-bundle-{}-{}.dic",
+            name,
+            std::process::id()
+        ));
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn tokenizer() -> Tokenizer<u16, Trie<u16, Info>> {
+        let mut dic = Trie::new();
+        let mut features = Features::new();
+        let feature = features
+            .insert("名詞,一般,*,*,*,*,すもも,スモモ,スモモ")
+            .unwrap();
+        dic.insert("すもも", Info::with_feature(0, 0, 100, feature));
+        let matrix = Matrix {
+            width: 1,
+            height: 1,
+            cost: vec![0],
+        };
+        let unknown = Unknown::from_reader(&b"DEFAULT 0 1 0\n"[..], &b"DEFAULT,0,0,1000,*\n"[..]);
+        let metadata = Metadata::new("test", "1.0");
+        Tokenizer::new(dic, matrix, unknown.unwrap(), features, metadata).unwrap()
+    }
+
+    #[test]
+    // "保存したファイルから読み込んで、そのまま解析できる"
+    fn test_save_and_load() {
+        let mut bytes = vec![];
+        let tokenizer = tokenizer().save_to_file(&mut bytes).unwrap();
+        assert_eq!(tokenizer.metadata().source, "test");
+
+        let loaded = Tokenizer::<u16, Trie<u16, Info>>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.metadata(), tokenizer.metadata());
+        let analyzed = loaded.analyze("すもも").unwrap();
+        let token = analyzed.iter().next().unwrap();
+        assert_eq!(token.cost, 100);
+        assert_eq!(token.feature.unwrap().base_form, "すもも");
+    }
+
+    #[test]
+    // "壊れたファイルや辞書の種類が異なるファイルはエラーになる"
+    fn test_corrupt() {
+        let mut bytes = vec![];
+        tokenizer().save_to_file(&mut bytes).unwrap();
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        let err = Tokenizer::<u16, Trie<u16, Info>>::from_bytes(&corrupt)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("チェックサム"));
+        assert!(Tokenizer::<u16, Trie<u16, Info>>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Tokenizer::<u8, Trie<u8, Info>>::from_bytes(&bytes).is_err());
+        assert!(Tokenizer::<u16, Trie<u16, Info>>::from_bytes(b"DMJD").is_err());
+    }
+
+    #[test]
+    // "ヘッダだけを読み込んで辞書の種類が分かる"
+    fn test_header() {
+        let path = temp_file("header");
+        tokenizer()
+            .save_to_file(File::create(&path).unwrap())
+            .unwrap();
+        let header = BundleHeader::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(header.dictionary.type_name(), "trie16");
+        assert_eq!(header.metadata.version, "1.0");
+    }
+
+    #[test]
+    // "メモリマップ形式の辞書もまとめて読み込める"
+    fn test_mapped() {
+        let mut da = DoubleArray::<u16, Info>::new();
+        da.insert("すもも", Info::new(0, 0, 100));
+        let dic = MappedDoubleArray::from_double_array(&da).unwrap();
+        let (_, matrix, unknown, features, metadata) = tokenizer().into_parts();
+        let tokenizer = Tokenizer::new(dic, matrix, unknown, features, metadata).unwrap();
+
+        let path = temp_file("mapped");
+        tokenizer
+            .save_to_file(File::create(&path).unwrap())
+            .unwrap();
+        let header = BundleHeader::load_from_file(&path).unwrap();
+        assert_eq!(header.dictionary.type_name(), "mapped16");
+        let loaded = Tokenizer::<u16, MappedDoubleArray<u16>>::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let analyzed = loaded.analyze("すもも").unwrap();
+        assert_eq!(analyzed.iter().next().unwrap().cost, 100);
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11016), "2000-02-29");
+        assert_eq!(format_date(20744), "2026-10-18");
+    }
+}
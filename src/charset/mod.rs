@@ -0,0 +1,230 @@
+use crate::error::{Error, Result};
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+mod jis0208;
+
+use self::jis0208::JIS0208;
+
+/// 辞書ソースの文字コード
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Charset {
+    Utf8,
+    /// JIS X 0208 と半角カタカナに対応する。JIS X 0212 (補助漢字) は扱わない。
+    EucJp,
+    /// JIS X 0208 と半角カタカナに対応する。機種依存文字は扱わない。
+    ShiftJis,
+}
+
+impl Default for Charset {
+    #[inline]
+    fn default() -> Self {
+        Charset::Utf8
+    }
+}
+
+impl FromStr for Charset {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "euc-jp" | "eucjp" => Ok(Charset::EucJp),
+            "shift-jis" | "sjis" => Ok(Charset::ShiftJis),
+            _ => Err(Error::format(format!(
+                "{} は対応していない文字コードです",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::EucJp => "EUC-JP",
+            Charset::ShiftJis => "Shift_JIS",
+        };
+        f.write_str(name)
+    }
+}
+
+/// JIS X 0208 の区点 (0 始まり) を文字にする。
+#[inline]
+fn jis0208(row: u8, cell: u8) -> Option<char> {
+    if row >= 94 || cell >= 94 {
+        return None;
+    }
+    match JIS0208[row as usize * 94 + cell as usize] {
+        0 => None,
+        code => std::char::from_u32(code as u32),
+    }
+}
+
+/// 半角カタカナ (0xA1〜0xDF) を文字にする。
+#[inline]
+fn half_width_katakana(byte: u8) -> Option<char> {
+    match byte {
+        0xA1..=0xDF => std::char::from_u32(0xFF61 + (byte - 0xA1) as u32),
+        _ => None,
+    }
+}
+
+impl Charset {
+    /// バイト列を文字列に変換する。不正なバイト列はエラーになる。
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        match self {
+            Charset::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(text) => Ok(text.to_owned()),
+                Err(e) => Err(self.invalid(e.valid_up_to())),
+            },
+            Charset::EucJp => self.decode_with(bytes, decode_euc_jp),
+            Charset::ShiftJis => self.decode_with(bytes, decode_shift_jis),
+        }
+    }
+
+    /// `decode` で一文字ずつ変換する。`decode` は文字と使ったバイト数を返す。
+    fn decode_with<F>(self, bytes: &[u8], decode: F) -> Result<String>
+    where
+        F: Fn(&[u8]) -> Option<(char, usize)>,
+    {
+        let mut text = String::with_capacity(bytes.len());
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match decode(&bytes[pos..]) {
+                Some((ch, len)) => {
+                    text.push(ch);
+                    pos += len;
+                }
+                None => return Err(self.invalid(pos)),
+            }
+        }
+        Ok(text)
+    }
+
+    #[inline]
+    fn invalid(self, pos: usize) -> Error {
+        Error::format(format!(
+            "{} として不正なバイト列です ({} バイト目)",
+            self,
+            pos + 1
+        ))
+    }
+
+    /// 一行を読み込み、文字列に変換して `buf` の後ろに追加する。末尾の改行は除く。
+    /// 読み込んだバイト数を返し、終端では 0 を返す。
+    pub(crate) fn read_line<R: BufRead>(self, reader: &mut R, buf: &mut String) -> Result<usize> {
+        let mut bytes = vec![];
+        let len = reader.read_until(b'\n', &mut bytes)?;
+        if bytes.last() == Some(&b'\n') {
+            bytes.pop();
+            if bytes.last() == Some(&b'\r') {
+                bytes.pop();
+            }
+        }
+        buf.push_str(&self.decode(&bytes)?);
+        Ok(len)
+    }
+}
+
+fn decode_euc_jp(bytes: &[u8]) -> Option<(char, usize)> {
+    match bytes[0] {
+        b @ 0x00..=0x7F => Some((b as char, 1)),
+        0x8E => half_width_katakana(*bytes.get(1)?).map(|ch| (ch, 2)),
+        b @ 0xA1..=0xFE => {
+            let trail = *bytes.get(1)?;
+            if trail < 0xA1 || trail == 0xFF {
+                return None;
+            }
+            jis0208(b - 0xA1, trail - 0xA1).map(|ch| (ch, 2))
+        }
+        _ => None,
+    }
+}
+
+fn decode_shift_jis(bytes: &[u8]) -> Option<(char, usize)> {
+    let lead = bytes[0];
+    let row = match lead {
+        0x00..=0x7F => return Some((lead as char, 1)),
+        0xA1..=0xDF => return half_width_katakana(lead).map(|ch| (ch, 1)),
+        0x81..=0x9F => (lead - 0x81) * 2,
+        0xE0..=0xEF => (lead - 0xC1) * 2,
+        _ => return None,
+    };
+    let (row, cell) = match *bytes.get(1)? {
+        trail @ 0x40..=0x7E => (row, trail - 0x40),
+        trail @ 0x80..=0x9E => (row, trail - 0x41),
+        trail @ 0x9F..=0xFC => (row + 1, trail - 0x9F),
+        _ => return None,
+    };
+    jis0208(row, cell).map(|ch| (ch, 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // "各文字コードで「すもも」と半角カタカナ、ASCII を変換する"
+    fn test_decode() {
+        let euc = b"\xA4\xB9\xA4\xE2\xA4\xE2,\x8E\xB1a";
+        assert_eq!(Charset::EucJp.decode(euc).unwrap(), "すもも,ｱa");
+        let sjis = b"\x82\xB7\x82\xE0\x82\xE0,\xB1a";
+        assert_eq!(Charset::ShiftJis.decode(sjis).unwrap(), "すもも,ｱa");
+        assert_eq!(Charset::Utf8.decode("すもも".as_bytes()).unwrap(), "すもも");
+    }
+
+    #[test]
+    // "二つの文字コードで同じ区点が同じ文字になる"
+    fn test_row_cell() {
+        // 1 区 1 点、16 区 1 点 (亜)、47 区 51 点 (腕)、84 区 6 点 (熙)
+        let cases = [
+            (b"\xA1\xA1", b"\x81\x40", '\u{3000}'),
+            (b"\xB0\xA1", b"\x88\x9F", '亜'),
+            (b"\xCF\xD3", b"\x98\x72", '腕'),
+            (b"\xF4\xA6", b"\xEA\xA4", '熙'),
+        ];
+        for (euc, sjis, ch) in &cases {
+            assert_eq!(Charset::EucJp.decode(*euc).unwrap(), ch.to_string());
+            assert_eq!(Charset::ShiftJis.decode(*sjis).unwrap(), ch.to_string());
+        }
+    }
+
+    #[test]
+    // "不正なバイト列は位置付きのエラーになる"
+    fn test_invalid() {
+        let err = Charset::EucJp.decode(b"a\xA4").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "EUC-JP として不正なバイト列です (2 バイト目)"
+        );
+        // 割り当てのない区点
+        assert!(Charset::EucJp.decode(b"\xA9\xA1").is_err());
+        assert!(Charset::ShiftJis.decode(b"\x82\x20").is_err());
+        assert!(Charset::ShiftJis.decode(b"\xF0\x40").is_err());
+        assert!(Charset::Utf8.decode(b"\xA4\xB9").is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("EUC-JP".parse::<Charset>().unwrap(), Charset::EucJp);
+        assert_eq!("shift_jis".parse::<Charset>().unwrap(), Charset::ShiftJis);
+        assert_eq!("utf8".parse::<Charset>().unwrap(), Charset::Utf8);
+        assert!("latin1".parse::<Charset>().is_err());
+    }
+
+    #[test]
+    // "改行を除いて一行ずつ読み込む"
+    fn test_read_line() {
+        let mut reader = &b"\xA4\xB9\r\n\xA4\xE2\n"[..];
+        let mut buf = String::new();
+        assert_eq!(Charset::EucJp.read_line(&mut reader, &mut buf).unwrap(), 4);
+        assert_eq!(buf, "す");
+        buf.clear();
+        Charset::EucJp.read_line(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, "も");
+        assert_eq!(Charset::EucJp.read_line(&mut reader, &mut buf).unwrap(), 0);
+    }
+}
@@ -1,38 +1,386 @@
+use crate::error::{Error, Result};
+use crate::info::Info;
+use crate::mapped_double_array::{self, MappedDoubleArray};
 use crate::prefix_map::PrefixMap;
-use bincode;
+use crate::recursive_hash_map::RecursiveHashMap;
+use crate::trie::Trie;
+use crate::DoubleArray;
+use fnv::FnvHasher;
 use serde::{de::DeserializeOwned, Serialize};
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+
+// -----------------------------------------------------------------------------
+
+// ヘッダの形式。識別子 `DMJD`、バージョン、データ構造、キー一文字のバイト数、
+// 値の型名の長さを 1 バイトずつ並べ、その後ろに値の型名を置く。
+// バージョンはデータ構造ごとに数える。
+const MAGIC: &[u8; 4] = b"DMJD";
+
+/// 辞書のデータ構造の種類
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DictKind {
+    DoubleArray,
+    HashMap,
+    Trie,
+    /// メモリマップ形式のダブル配列
+    Mapped,
+}
+
+impl DictKind {
+    #[inline]
+    fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(DictKind::DoubleArray),
+            1 => Some(DictKind::HashMap),
+            2 => Some(DictKind::Trie),
+            3 => Some(DictKind::Mapped),
+            _ => None,
+        }
+    }
+
+    /// 保存の形式のバージョン。ダブル配列はバージョン 2 から部分ごとにチェックサム付きで保存する。
+    #[inline]
+    fn version(self) -> u8 {
+        match self {
+            DictKind::DoubleArray => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// ファイルに保存できる辞書。ヘッダに記録するデータ構造を表す。
+pub trait DictType {
+    const KIND: DictKind;
+}
+
+impl<K, V> DictType for DoubleArray<K, V> {
+    const KIND: DictKind = DictKind::DoubleArray;
+}
+
+impl<K: Eq + Hash, V> DictType for RecursiveHashMap<K, V> {
+    const KIND: DictKind = DictKind::HashMap;
+}
+
+impl<K, V> DictType for Trie<K, V> {
+    const KIND: DictKind = DictKind::Trie;
+}
+
+impl<K> DictType for MappedDoubleArray<K> {
+    const KIND: DictKind = DictKind::Mapped;
+}
+
+/// 辞書に保存できる値。ヘッダに記録する型名を表す。
+pub trait DictValue {
+    const NAME: &'static str;
+}
+
+impl DictValue for Info {
+    const NAME: &'static str = "Info";
+}
+
+/// 辞書ファイルの先頭に保存する、辞書の種類を表すヘッダ
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DictHeader {
+    pub kind: DictKind,
+    /// キー一文字のバイト数 (u8 は 1、u16 は 2、char は 4)
+    pub key_width: u8,
+    /// 値の型名
+    pub value: String,
+}
+
+impl DictHeader {
+    /// 辞書の型からヘッダを作る。
+    pub fn of<K, V: DictValue, D: DictType>() -> Self {
+        DictHeader {
+            kind: D::KIND,
+            key_width: mem::size_of::<K>() as u8,
+            value: V::NAME.to_owned(),
+        }
+    }
+
+    /// `build-dict` などで指定するタイプ名 (`array16` など)
+    pub fn type_name(&self) -> String {
+        let kind = match self.kind {
+            DictKind::DoubleArray => "array",
+            DictKind::HashMap => "hash",
+            DictKind::Trie => "trie",
+            DictKind::Mapped => "mapped",
+        };
+        format!("{}{}", kind, self.key_width as usize * 8)
+    }
+
+    /// ファイルの先頭からヘッダを読み込む。
+    pub fn load_from_file(file: &str) -> Result<Self> {
+        let read = || DictHeader::read(&mut File::open(file)?);
+        read().map_err(|e: Error| e.in_file(file))
+    }
+
+    pub(crate) fn write<W: Write>(&self, file: &mut W) -> Result<()> {
+        file.write_all(MAGIC)?;
+        let value = self.value.as_bytes();
+        let kind = self.kind as u8;
+        let version = self.kind.version();
+        file.write_all(&[version, kind, self.key_width, value.len() as u8])?;
+        file.write_all(value)?;
+        Ok(())
+    }
+
+    /// ヘッダを読み込む。メモリマップ形式の辞書は `DMJD` のヘッダを持たないため、
+    /// 独自の先頭部分から判別する。
+    pub(crate) fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 4];
+        read_header_bytes(reader, &mut magic)?;
+        if magic == *mapped_double_array::MAGIC {
+            let mut words = [0; 12];
+            read_header_bytes(reader, &mut words)?;
+            return Ok(DictHeader {
+                kind: DictKind::Mapped,
+                key_width: words[4],
+                value: Info::NAME.to_owned(),
+            });
+        }
+        DictHeader::read_after_magic(reader, magic)
+    }
+
+    fn read_after_magic<R: Read>(reader: &mut R, magic: [u8; 4]) -> Result<Self> {
+        if magic != *MAGIC {
+            return Err(Error::format(
+                "辞書のヘッダがありません。辞書を作り直してください",
+            ));
+        }
+        let mut fields = [0; 4];
+        read_header_bytes(reader, &mut fields)?;
+        let [version, kind, key_width, value_len] = fields;
+        let kind =
+            DictKind::from_u8(kind).ok_or_else(|| Error::format("不明なデータ構造の辞書です"))?;
+        if version != kind.version() {
+            let message = format!(
+                "対応していないバージョンです ({})。辞書を作り直してください",
+                version
+            );
+            return Err(Error::format(message));
+        }
+        let mut value = vec![0; value_len as usize];
+        read_header_bytes(reader, &mut value)?;
+        let value =
+            String::from_utf8(value).map_err(|_| Error::format("辞書のヘッダが壊れています"))?;
+        Ok(DictHeader {
+            kind,
+            key_width,
+            value,
+        })
+    }
+
+    /// 読み込もうとしている辞書の型と一致するかを確かめる。
+    pub(crate) fn check<K, V: DictValue, D: DictType>(&self) -> Result<()> {
+        let expected = DictHeader::of::<K, V, D>();
+        if *self == expected {
+            Ok(())
+        } else {
+            Err(Error::format(format!(
+                "辞書のタイプが一致しません ({} を指定しましたが、{} の辞書です)",
+                expected.type_name(),
+                self.type_name()
+            )))
+        }
+    }
+}
+
+/// ヘッダを読み込む。ファイルが短すぎる場合はヘッダがないものとして扱う。
+fn read_header_bytes<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            Error::format("辞書のヘッダがありません。辞書を作り直してください")
+        }
+        _ => Error::from(e),
+    })
+}
+
+// -----------------------------------------------------------------------------
+
+// チェックサム付きの部分の形式。バイト数 (u64)、FNV-1a (u64)、bincode で直列化した値の順に並べる。
+
+#[inline]
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 値をチェックサム付きで書き出す。
+pub(crate) fn write_section<W: Write, T: Serialize>(file: &mut W, value: &T) -> Result<()> {
+    let bytes = bincode::serialize(value)?;
+    file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    file.write_all(&checksum(&bytes).to_le_bytes())?;
+    file.write_all(&bytes)?;
+    Ok(())
+}
+
+/// `write_section` で書き出した値を読み込む。`name` はエラーメッセージに使う。
+pub(crate) fn read_section<R: Read, T: DeserializeOwned>(reader: &mut R, name: &str) -> Result<T> {
+    let truncated = || Error::format(format!("{} の途中でファイルが終わっています", name));
+    let mut fields = [0; 16];
+    reader.read_exact(&mut fields).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => truncated(),
+        _ => Error::from(e),
+    })?;
+    let mut len = [0; 8];
+    len.copy_from_slice(&fields[..8]);
+    let len = u64::from_le_bytes(len);
+    let mut sum = [0; 8];
+    sum.copy_from_slice(&fields[8..]);
+
+    // 壊れたバイト数で大きな領域を確保しないよう、読めた分だけ受け取る
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(truncated());
+    }
+    if checksum(&bytes) != u64::from_le_bytes(sum) {
+        let message = format!("{} が壊れています (チェックサムが一致しません)", name);
+        return Err(Error::format(message));
+    }
+    Ok(bincode::deserialize(&bytes)?)
+}
 
 // -----------------------------------------------------------------------------
 
 pub trait SaveDict<K, V>: PrefixMap<K, V> {
-    fn save_to_file<W: Write>(self, file: W) -> Self;
+    fn save_to_file<W: Write>(self, file: W) -> Result<Self>
+    where
+        Self: Sized;
 }
 
 impl<K, V, D> SaveDict<K, V> for D
 where
-    D: PrefixMap<K, V> + Serialize,
+    V: DictValue,
+    D: PrefixMap<K, V> + Serialize + DictType,
 {
-    fn save_to_file<W: Write>(self, file: W) -> Self {
-        bincode::serialize_into(file, &self).expect("保存に失敗しました。");
-        self
+    fn save_to_file<W: Write>(self, mut file: W) -> Result<Self> {
+        DictHeader::of::<K, V, D>().write(&mut file)?;
+        bincode::serialize_into(file, &self)?;
+        Ok(self)
     }
 }
 
 // -----------------------------------------------------------------------------
 
 pub trait LoadDict<K, V>: PrefixMap<K, V> {
-    fn load_from_file(file: &str) -> Self;
+    /// `SaveDict::save_to_file` で書き出した内容を読み込む。
+    fn load_from_reader<R: Read>(reader: R) -> Result<Self>
+    where
+        Self: Sized;
+
+    fn load_from_file(file: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let load = || Self::load_from_reader(BufReader::new(File::open(file)?));
+        load().map_err(|e: Error| e.in_file(file))
+    }
 }
 
 impl<K, V, D> LoadDict<K, V> for D
 where
-    D: PrefixMap<K, V> + DeserializeOwned,
+    V: DictValue,
+    D: PrefixMap<K, V> + DeserializeOwned + DictType,
 {
-    fn load_from_file(file: &str) -> Self {
-        let file = File::open(file).expect("ファイルが開けません");
-        let file = BufReader::new(file);
-        bincode::deserialize_from(file).expect("辞書の復元に失敗しました。")
+    fn load_from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        DictHeader::read(&mut reader)?.check::<K, V, D>()?;
+        Ok(bincode::deserialize_from(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefix_map::PrefixMapInsert;
+
+    fn temp_file(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("dotamoji-{}-{}.dic", name, std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    // "保存した辞書のタイプをヘッダから判別できる"
+    fn test_header() {
+        let path = temp_file("header");
+        let mut trie = Trie::<u16, Info>::new();
+        trie.insert("すもも", Info::new(1, 2, 3));
+        trie.save_to_file(File::create(&path).unwrap()).unwrap();
+
+        let header = DictHeader::load_from_file(&path).unwrap();
+        assert_eq!(header, DictHeader::of::<u16, Info, Trie<u16, Info>>());
+        assert_eq!(header.type_name(), "trie16");
+
+        let trie = Trie::<u16, Info>::load_from_file(&path).unwrap();
+        assert_eq!(trie.get("すもも").unwrap()[0].cost, 3);
+
+        // 異なるタイプでは読み込めない
+        let err = DoubleArray::<u16, Info>::load_from_file(&path)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("array16 を指定しましたが、trie16 の辞書です"));
+        assert!(Trie::<u8, Info>::load_from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    // "バージョンはデータ構造ごとに確かめる"
+    fn test_version() {
+        let path = temp_file("version");
+        let mut trie = Trie::<u8, Info>::new();
+        trie.insert("すもも", Info::new(1, 2, 3));
+        trie.save_to_file(File::create(&path).unwrap()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[4], 1);
+        assert!(Trie::<u8, Info>::load_from_file(&path).is_ok());
+
+        let mut da = DoubleArray::<u8, Info>::new();
+        da.insert("すもも", Info::new(1, 2, 3));
+        da.save_to_file(File::create(&path).unwrap()).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[4], 2);
+
+        // チェックサムのない古い形式のダブル配列は読み込めない
+        bytes[4] = 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let err = DoubleArray::<u8, Info>::load_from_file(&path)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("対応していないバージョンです (1)"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    // "ヘッダのないファイルはエラーになる"
+    fn test_no_header() {
+        let path = temp_file("no-header");
+        std::fs::write(&path, b"DM").unwrap();
+        let err = DictHeader::load_from_file(&path).err().unwrap();
+        assert!(err.to_string().contains("ヘッダがありません"));
+        std::fs::write(&path, b"\x01\x00\x00\x00\x00\x00\x00\x00").unwrap();
+        assert!(Trie::<u16, Info>::load_from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    // "メモリマップ形式の辞書も判別できる"
+    fn test_mapped_header() {
+        let path = temp_file("mapped-header");
+        let da = DoubleArray::<char, Info>::new();
+        MappedDoubleArray::from_double_array(&da)
+            .unwrap()
+            .save_to_file(File::create(&path).unwrap())
+            .unwrap();
+        let header = DictHeader::load_from_file(&path).unwrap();
+        assert_eq!(header.type_name(), "mapped32");
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -1,8 +1,13 @@
-use crate::as_chars::{AsChars, AsUsize};
-use crate::prefix_map::PrefixMap;
+use crate::as_chars::{AsChars, AsUsize, CodeUnit};
+use crate::dictionary::{self, DictHeader, DictValue, LoadDict, SaveDict};
+use crate::error::{Error, Result};
+use crate::fuzzy::Levenshtein;
+use crate::prefix_map::{remove_one, take_values, PrefixMap, PrefixMapInsert, PrefixMapMut};
 use crate::search_cache::{NoCache, SearchCache2};
+use serde::{de::DeserializeOwned, Serialize};
 use std::cmp::{max, min};
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 
 #[derive(Eq, PartialEq)]
@@ -14,11 +19,13 @@ enum Index {
     OutOfRange,
 }
 
-#[derive(Serialize, Deserialize)]
 pub struct DoubleArray<K, V, C> {
     pub(crate) base: Vec<u32>,
     pub(crate) check: Vec<u32>,
     pub(crate) data: Vec<Vec<V>>,
+    /// 各位置の子の数。取り除くときに子が残っているかを調べるのに使う。
+    /// 保存はせず、`check` から数え直す。
+    child_count: Vec<u32>,
     phantom: PhantomData<K>,
     search_cache: C,
 }
@@ -30,6 +37,7 @@ impl<K, V, C: SearchCache2> DoubleArray<K, V, C> {
             base: vec![0, 0],
             check: vec![0, 0],
             data: vec![vec![], vec![]],
+            child_count: vec![0, 0],
             phantom: PhantomData,
             search_cache: C::new(2),
         }
@@ -39,10 +47,17 @@ impl<K, V, C: SearchCache2> DoubleArray<K, V, C> {
 impl<K: AsUsize, V> DoubleArray<K, V, NoCache> {
     #[inline]
     pub fn from_raw_parts(base: Vec<u32>, check: Vec<u32>, data: Vec<Vec<V>>) -> Self {
+        let mut child_count = vec![0; check.len()];
+        for &parent in check.iter().skip(2).filter(|&&parent| parent != 0) {
+            if let Some(count) = child_count.get_mut(parent as usize) {
+                *count += 1;
+            }
+        }
         Self {
             base,
             check,
             data,
+            child_count,
             phantom: PhantomData,
             search_cache: NoCache,
         }
@@ -89,6 +104,22 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
         }
     }
 
+    #[inline]
+    fn longest_match<I: AsChars<K>>(&self, key: I) -> Option<(usize, &[V])> {
+        let mut current_ix = 1;
+        let mut longest = None;
+        for (ix, ch) in key.as_chars().enumerate() {
+            match self.next_index(current_ix, ch) {
+                (Index::Transit, next_ix) => current_ix = next_ix,
+                _ => break,
+            }
+            if !self.data[current_ix].is_empty() {
+                longest = Some((ix + 1, &self.data[current_ix][..]));
+            }
+        }
+        longest
+    }
+
     #[inline]
     fn next_index(&self, current_index: usize, ch: K) -> (Index, usize) {
         let current_base = self.base[current_index];
@@ -135,10 +166,44 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
         self.data[current_ix].push(value);
     }
 
+    /// キーの位置の値を `f` で取り除く。値も子もなくなった位置は空きに戻し、
+    /// 子がなくなった位置の base は 0 に戻して、後から登録するときに使えるようにする。
+    fn remove_with<I: AsChars<K>, R, F: FnOnce(&mut Vec<V>) -> Option<R>>(
+        &mut self,
+        key: I,
+        f: F,
+    ) -> Option<R> {
+        let mut path = vec![1];
+        let mut current_ix = 1;
+        for ch in key.as_chars() {
+            match self.next_index(current_ix, ch) {
+                (Index::Transit, next_ix) => current_ix = next_ix,
+                _ => return None,
+            }
+            path.push(current_ix);
+        }
+        let result = f(&mut self.data[current_ix])?;
+
+        while let Some(ix) = path.pop() {
+            if self.child_count[ix] > 0 {
+                break;
+            }
+            self.base[ix] = 0;
+            if ix == 1 || !self.data[ix].is_empty() {
+                break;
+            }
+            self.child_count[self.check[ix] as usize] -= 1;
+            self.check[ix] = 0;
+            self.search_cache.unmark(ix);
+        }
+        Some(result)
+    }
+
     #[inline]
     fn update(&mut self, current_ix: usize, next_ix: usize) -> usize {
         self.base[next_ix] = 0;
         self.check[next_ix] = current_ix as u32;
+        self.child_count[current_ix] += 1;
         self.search_cache.mark(next_ix);
         next_ix
     }
@@ -180,6 +245,7 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
             let src_base = self.base[src_ix] as usize;
             self.base[dst_ix] = self.base[src_ix];
             self.check[dst_ix] = self.check[src_ix];
+            self.child_count[dst_ix] = self.child_count[src_ix];
             self.search_cache.mark(dst_ix);
             self.data.swap(src_ix, dst_ix);
 
@@ -197,6 +263,7 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
             // 5. 旧遷移先ノードの base, check, data をリセット
             self.base[src_ix] = 0;
             self.check[src_ix] = 0;
+            self.child_count[src_ix] = 0;
             self.search_cache.unmark(src_ix);
         }
         new_base as usize + ch.as_usize()
@@ -232,6 +299,7 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
 
         self.base.resize(size, 0);
         self.check.resize(size, 0);
+        self.child_count.resize(size, 0);
         self.search_cache.extend(size);
         let n = self.data.len();
         self.data.extend((n..size).map(|_| vec![]));
@@ -242,6 +310,126 @@ impl<K: AsUsize, V, C: SearchCache2> DoubleArray<K, V, C> {
     }
 }
 
+impl<K: AsUsize, V, C> DoubleArray<K, V, C> {
+    /// 構造が壊れていないかを検査し、見つかった問題を位置の順に返す。
+    pub fn verify(&self) -> Vec<Defect> {
+        let (base, check) = (&self.base, &self.check);
+        let len = check.len();
+        if base.len() != len || self.data.len() != len {
+            return vec![Defect::LengthMismatch {
+                base: base.len(),
+                check: len,
+                data: self.data.len(),
+            }];
+        }
+        if len < 2 {
+            return vec![Defect::NoRoot];
+        }
+
+        let mut defects = vec![];
+        let mut reported = vec![false; len];
+        for index in 0..len {
+            if base[index] as usize >= len {
+                defects.push(Defect::BaseOutOfRange {
+                    index,
+                    base: base[index] as usize,
+                });
+                reported[index] = true;
+            }
+            if index < 2 || check[index] == 0 {
+                continue;
+            }
+            let parent = check[index] as usize;
+            let defect = if parent >= len {
+                Defect::ParentOutOfRange { index, parent }
+            } else if base[parent] == 0 {
+                Defect::ZeroBase { index, parent }
+            } else if index < base[parent] as usize || index - base[parent] as usize > K::MAX {
+                Defect::InvalidTransition { index, parent }
+            } else {
+                continue;
+            };
+            defects.push(defect);
+            reported[index] = true;
+        }
+
+        // ルートから辿れない位置に値や遷移が残っていれば孤立している
+        let mut reachable = vec![false; len];
+        each_node::<K, _>(base, check, |_, ix| reachable[ix] = true);
+        for index in 0..len {
+            let used = base[index] != 0 || check[index] != 0 || !self.data[index].is_empty();
+            if used && !reachable[index] && !reported[index] {
+                defects.push(Defect::Orphan { index });
+            }
+        }
+        defects.sort_by_key(Defect::index);
+        defects
+    }
+}
+
+/// `DoubleArray::verify` で見つかった構造の問題
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Defect {
+    /// `base`、`check`、値の配列の長さが一致しない
+    LengthMismatch {
+        base: usize,
+        check: usize,
+        data: usize,
+    },
+    /// ルートの位置がない
+    NoRoot,
+    /// `base` が配列の外を指している
+    BaseOutOfRange { index: usize, base: usize },
+    /// `check` が配列の外を指している
+    ParentOutOfRange { index: usize, parent: usize },
+    /// `check` が指す遷移元の `base` が 0
+    ZeroBase { index: usize, parent: usize },
+    /// 遷移元の `base` からの差がキーの文字の範囲に収まらない
+    InvalidTransition { index: usize, parent: usize },
+    /// ルートから辿れない位置が使われている
+    Orphan { index: usize },
+}
+
+impl Defect {
+    /// 問題のある位置。配列全体の問題では 0 を返す。
+    pub fn index(&self) -> usize {
+        match *self {
+            Defect::LengthMismatch { .. } | Defect::NoRoot => 0,
+            Defect::BaseOutOfRange { index, .. }
+            | Defect::ParentOutOfRange { index, .. }
+            | Defect::ZeroBase { index, .. }
+            | Defect::InvalidTransition { index, .. }
+            | Defect::Orphan { index } => index,
+        }
+    }
+}
+
+impl fmt::Display for Defect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Defect::LengthMismatch { base, check, data } => write!(
+                f,
+                "配列の長さが一致しません (base: {}、check: {}、値: {})",
+                base, check, data
+            ),
+            Defect::NoRoot => write!(f, "ルートがありません"),
+            Defect::BaseOutOfRange { index, base } => {
+                write!(f, "{}: base が範囲外です ({})", index, base)
+            }
+            Defect::ParentOutOfRange { index, parent } => {
+                write!(f, "{}: check が範囲外です ({})", index, parent)
+            }
+            Defect::ZeroBase { index, parent } => {
+                write!(f, "{}: 遷移元 {} の base が 0 です", index, parent)
+            }
+            Defect::InvalidTransition { index, parent } => {
+                write!(f, "{}: 遷移元 {} からの遷移が不正です", index, parent)
+            }
+            Defect::Orphan { index } => write!(f, "{}: ルートから辿れません", index),
+        }
+    }
+}
+
 impl<K, V: Debug, C> DoubleArray<K, V, C> {
     pub fn show_debug(&self) {
         use std::char::from_u32;
@@ -263,6 +451,106 @@ impl<K, V: Debug, C> DoubleArray<K, V, C> {
     }
 }
 
+/// 親の位置ごとにまとめた子の位置の一覧。
+///
+/// ダブル配列は子の一覧を持たないため、辿る前に `check` から一度だけ作る。
+/// 子の位置の順は文字の順と同じになる。
+pub(crate) struct Children(Vec<(u32, u32)>);
+
+impl Children {
+    pub(crate) fn new(base: &[u32], check: &[u32]) -> Self {
+        let mut edges: Vec<(u32, u32)> = (2..check.len().min(base.len()))
+            .filter(|&ix| check[ix] != 0)
+            .map(|ix| (check[ix], ix as u32))
+            .collect();
+        edges.sort_by_key(|&(parent, _)| parent);
+        Children(edges)
+    }
+
+    /// `ix` の子を文字の順に、文字と位置の組で返す。
+    pub(crate) fn of<'a, K: AsUsize>(
+        &'a self,
+        base: &[u32],
+        ix: usize,
+    ) -> impl Iterator<Item = (K, usize)> + 'a {
+        let start = self
+            .0
+            .partition_point(|&(parent, _)| (parent as usize) < ix);
+        let end = self
+            .0
+            .partition_point(|&(parent, _)| (parent as usize) <= ix);
+        let node_base = base[ix] as usize;
+        self.0[start..end]
+            .iter()
+            .map(|&(_, child)| child as usize)
+            .filter(move |&child| {
+                node_base != 0 && child != ix && child >= node_base && child - node_base <= K::MAX
+            })
+            .map(move |child| (K::from_usize(child - node_base), child))
+    }
+}
+
+/// `base` と `check` からキーを復元し、ルートから辿れる各位置とそのキーを辞書順に `f` に渡す。
+pub(crate) fn each_node<K: AsUsize, F: FnMut(&[K], usize)>(base: &[u32], check: &[u32], mut f: F) {
+    if base.len() > 1 {
+        let children = Children::new(base, check);
+        each_descendant(base, &children, 1, &mut vec![], &mut |key, ix| {
+            f(key, ix);
+            true
+        });
+    }
+}
+
+/// `ix` の位置から辿れる位置を、`key` に続くキーとともに辞書順に `f` に渡す。
+/// `f` が `false` を返すと打ち切り、`false` を返す。
+pub(crate) fn each_descendant<K: AsUsize, F: FnMut(&[K], usize) -> bool>(
+    base: &[u32],
+    children: &Children,
+    ix: usize,
+    key: &mut Vec<K>,
+    f: &mut F,
+) -> bool {
+    if !f(key, ix) {
+        return false;
+    }
+    for (ch, child) in children.of(base, ix) {
+        key.push(ch);
+        if !each_descendant(base, children, child, key, f) {
+            return false;
+        }
+        key.pop();
+    }
+    true
+}
+
+/// `ix` の位置から辿れる位置のうち、編集距離が上限に収まる位置を、キーと距離とともに辞書順に `f` に渡す。
+/// `f` が `false` を返すと打ち切り、`false` を返す。
+pub(crate) fn each_fuzzy<K: AsUsize + CodeUnit, F: FnMut(&[K], usize, usize) -> bool>(
+    base: &[u32],
+    children: &Children,
+    ix: usize,
+    key: &mut Vec<K>,
+    lev: &mut Levenshtein<K>,
+    f: &mut F,
+) -> bool {
+    if let Some(distance) = lev.distance() {
+        if !f(key, ix, distance) {
+            return false;
+        }
+    }
+    for (ch, child) in children.of(base, ix) {
+        let reachable = lev.push(ch);
+        key.push(ch);
+        let cont = !reachable || each_fuzzy(base, children, child, key, lev, f);
+        key.pop();
+        lev.pop();
+        if !cont {
+            return false;
+        }
+    }
+    true
+}
+
 impl<K, V, C: SearchCache2> Default for DoubleArray<K, V, C> {
     #[inline]
     fn default() -> Self {
@@ -281,19 +569,143 @@ impl<K: AsUsize, V, C: SearchCache2> PrefixMap<K, V> for DoubleArray<K, V, C> {
         self.get(key)
     }
 
+    #[inline]
+    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, f: F) {
+        self.each_prefix(key, f)
+    }
+
+    #[inline]
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[V])> {
+        self.longest_match(key)
+    }
+
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, mut f: F)
+    where
+        V: 'a,
+    {
+        each_node(&self.base, &self.check, |key, ix| match self.data.get(ix) {
+            Some(values) if !values.is_empty() => f(key, values),
+            _ => (),
+        });
+    }
+
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+        V: 'a,
+    {
+        let mut lev = Levenshtein::new(key.as_chars().collect(), max_distance);
+        let data = &self.data;
+        let mut visit = |key: &[K], ix: usize, distance| match data.get(ix) {
+            Some(values) if !values.is_empty() => f(key, distance, values),
+            _ => true,
+        };
+        each_fuzzy(
+            &self.base,
+            &Children::new(&self.base, &self.check),
+            1,
+            &mut vec![],
+            &mut lev,
+            &mut visit,
+        );
+    }
+
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        prefix: T,
+        mut f: F,
+    ) where
+        V: 'a,
+    {
+        let mut key = vec![];
+        let mut current_ix = 1;
+        for ch in prefix.as_chars() {
+            match self.next_index(current_ix, ch) {
+                (Index::Transit, next_ix) => current_ix = next_ix,
+                _ => return,
+            }
+            key.push(ch);
+        }
+        let data = &self.data;
+        each_descendant(
+            &self.base,
+            &Children::new(&self.base, &self.check),
+            current_ix,
+            &mut key,
+            &mut |key, ix| match data.get(ix) {
+                Some(values) if !values.is_empty() => f(key, values),
+                _ => true,
+            },
+        );
+    }
+}
+
+impl<K: AsUsize, V, C: SearchCache2> PrefixMapInsert<K, V> for DoubleArray<K, V, C> {
     #[inline]
     fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
         self.insert(key, value)
     }
+}
 
+impl<K: AsUsize, V, C: SearchCache2> PrefixMapMut<K, V> for DoubleArray<K, V, C> {
     #[inline]
-    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, f: F) {
-        self.each_prefix(key, f)
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<V>> {
+        self.remove_with(key, take_values)
+    }
+
+    #[inline]
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let remove = |data: &mut Vec<V>| remove_one(data, value);
+        self.remove_with(key, remove).is_some()
+    }
+}
+
+// 保存するときは base、check、値をそれぞれチェックサム付きで書き出す。
+// 読み込むときはチェックサムと配列の長さを確かめる。構造全体の検査は `verify` で行う。
+
+impl<K: AsUsize, V> SaveDict<K, V> for DoubleArray<K, V, NoCache>
+where
+    V: Serialize + DictValue,
+{
+    fn save_to_file<W: Write>(self, mut file: W) -> Result<Self> {
+        DictHeader::of::<K, V, Self>().write(&mut file)?;
+        dictionary::write_section(&mut file, &self.base)?;
+        dictionary::write_section(&mut file, &self.check)?;
+        dictionary::write_section(&mut file, &self.data)?;
+        file.flush()?;
+        Ok(self)
+    }
+}
+
+impl<K: AsUsize, V> LoadDict<K, V> for DoubleArray<K, V, NoCache>
+where
+    V: DeserializeOwned + DictValue,
+{
+    fn load_from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        DictHeader::read(&mut reader)?.check::<K, V, Self>()?;
+        let base: Vec<u32> = dictionary::read_section(&mut reader, "base")?;
+        let check: Vec<u32> = dictionary::read_section(&mut reader, "check")?;
+        let data: Vec<Vec<V>> = dictionary::read_section(&mut reader, "値")?;
+        if base.len() != check.len() || data.len() != check.len() || check.len() < 2 {
+            return Err(Error::format("辞書の配列の長さが一致しません"));
+        }
+        Ok(DoubleArray::from_raw_parts(base, check, data))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::Defect;
+    use crate::dictionary::{LoadDict, SaveDict};
+    use crate::info::Info;
+    use crate::prefix_map::{owned_keys, PrefixMap, PrefixMapMut};
     use crate::search_cache::NoCache;
 
     type DoubleArray<T> = super::DoubleArray<u8, T, NoCache>;
@@ -398,4 +810,159 @@ mod tests {
         });
         assert_eq!(vec, vec![(1, vec![4, 5]), (3, vec![1])]);
     }
+
+    #[test]
+    // "登録したキーと値を辞書順に列挙する"
+    fn test_each_entry() {
+        let mut pt = DoubleArray::new();
+        pt.insert("ba", 1);
+        pt.insert("abc", 2);
+        pt.insert("a", 3);
+        pt.insert("ba", 4);
+        pt.insert("ac", 5);
+
+        let mut vec = vec![];
+        pt.each_entry(|key, data| {
+            vec.push((String::from_utf8(key.to_owned()).unwrap(), data.to_owned()));
+        });
+        let expected = owned_keys(vec![
+            ("a", vec![3]),
+            ("abc", vec![2]),
+            ("ac", vec![5]),
+            ("ba", vec![1, 4]),
+        ]);
+        assert_eq!(vec, expected);
+    }
+
+    #[test]
+    // "正しく構築した配列には問題がない"
+    fn test_verify() {
+        let mut pt = DoubleArray::new();
+        pt.insert("abc", 1);
+        pt.insert("ad", 2);
+        pt.insert("ac", 3);
+        assert_eq!(pt.verify(), vec![]);
+    }
+
+    #[test]
+    // "壊れた配列の問題を見つける"
+    fn test_verify_broken() {
+        let mut pt = DoubleArray::new();
+        pt.insert("ab", 1);
+        let a = (2..pt.check.len()).find(|&ix| pt.check[ix] == 1).unwrap();
+        let b = (2..pt.check.len())
+            .find(|&ix| pt.check[ix] as usize == a)
+            .unwrap();
+
+        // 遷移元の base を消すと、その先は辿れなくなる
+        let mut broken = DoubleArray::from_raw_parts(pt.base.clone(), pt.check.clone(), vec![]);
+        assert_eq!(
+            broken.verify(),
+            vec![Defect::LengthMismatch {
+                base: pt.base.len(),
+                check: pt.check.len(),
+                data: 0
+            }]
+        );
+        broken.data = pt.data.clone();
+        broken.base[a] = 0;
+        assert_eq!(
+            broken.verify(),
+            vec![Defect::ZeroBase {
+                index: b,
+                parent: a
+            }]
+        );
+
+        broken.base[a] = pt.base[a];
+        broken.check[b] = 1_000_000;
+        assert_eq!(
+            broken.verify(),
+            vec![Defect::ParentOutOfRange {
+                index: b,
+                parent: 1_000_000
+            }]
+        );
+
+        // どこからも指されていない位置に値がある
+        broken.check[b] = pt.check[b];
+        broken.data[0].push(9);
+        assert_eq!(broken.verify(), vec![Defect::Orphan { index: 0 }]);
+    }
+
+    #[test]
+    // "保存したファイルの破損をチェックサムで検出する"
+    fn test_checksum() {
+        let mut pt = super::DoubleArray::<u8, Info, NoCache>::new();
+        pt.insert("ab", Info::new(1, 2, 3));
+        let mut bytes = vec![];
+        pt.save_to_file(&mut bytes).unwrap();
+
+        let loaded = super::DoubleArray::<u8, Info, NoCache>::load_from_reader(&bytes[..]).unwrap();
+        assert_eq!(loaded.get("ab").unwrap()[0].cost, 3);
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let err = super::DoubleArray::<u8, Info, NoCache>::load_from_reader(&bytes[..])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("値 が壊れています"));
+
+        let err = super::DoubleArray::<u8, Info, NoCache>::load_from_reader(&bytes[..last])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("途中でファイルが終わっています"));
+    }
+
+    #[test]
+    // "キーと値を取り除き、空いた位置を再利用する"
+    fn test_remove() {
+        let mut pt = DoubleArray::new();
+        pt.insert("abc", 1);
+        pt.insert("ab", 2);
+        pt.insert("ad", 3);
+        pt.insert("b", 4);
+        pt.insert("ab", 5);
+
+        assert_eq!(pt.remove("ab"), Some(vec![2, 5]));
+        assert_eq!(pt.get("ab"), None);
+        assert_eq!(pt.get("abc"), Some(&[1][..]));
+        assert_eq!(pt.remove("ab"), None);
+        assert_eq!(pt.remove("a"), None);
+        assert_eq!(pt.remove("x"), None);
+
+        assert!(pt.remove_value("ad", &3));
+        assert!(!pt.remove_value("ad", &3));
+        assert!(!pt.remove_value("b", &9));
+        assert_eq!(pt.get("ad"), None);
+        assert_eq!(pt.remove("abc"), Some(vec![1]));
+        assert_eq!(pt.verify(), vec![]);
+        assert_eq!(pt.count(), 1);
+
+        // "a" 以下の枝は取り除かれ、"b" だけが残る
+        let used = pt.check.iter().filter(|&&c| c != 0).count();
+        assert_eq!(used, 1);
+        let len = pt.check.len();
+        pt.insert("ad", 6);
+        assert_eq!(pt.check.len(), len);
+        assert_eq!(pt.get("ad"), Some(&[6][..]));
+        assert_eq!(pt.get("b"), Some(&[4][..]));
+        assert_eq!(pt.verify(), vec![]);
+    }
+
+    #[test]
+    // "子の数は位置を移しても引き継ぎ、配列から作り直したときは数え直す"
+    fn test_remove_all() {
+        let mut pt = DoubleArray::new();
+        let keys: Vec<_> = (0..200).map(|i| (i * 7919 % 1000).to_string()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            pt.insert(key.as_str(), i);
+        }
+        let mut pt = DoubleArray::from_raw_parts(pt.base, pt.check, pt.data);
+        for key in &keys {
+            assert!(pt.remove(key.as_str()).is_some());
+        }
+        assert_eq!(pt.check.iter().filter(|&&c| c != 0).count(), 0);
+        assert_eq!(pt.count(), 0);
+    }
 }
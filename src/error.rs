@@ -0,0 +1,121 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// ファイルの読み書きに失敗した
+    Io(io::Error),
+    /// 辞書などの直列化・復元に失敗した
+    Serialize(bincode::Error),
+    /// 入力の形式が正しくない
+    Format(String),
+    /// 形態素解析ができない
+    Analyze(String),
+}
+
+/// このクレートのエラー。原因に加えて、分かる場合はファイル名と行番号を持つ。
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    file: Option<String>,
+    line: Option<usize>,
+}
+
+impl Error {
+    #[inline]
+    pub fn new(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            file: None,
+            line: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn format<S: Into<String>>(message: S) -> Self {
+        Error::new(ErrorKind::Format(message.into()))
+    }
+
+    #[inline]
+    pub(crate) fn analyze<S: Into<String>>(message: S) -> Self {
+        Error::new(ErrorKind::Analyze(message.into()))
+    }
+
+    /// ファイル名を設定する。すでに設定されている場合は変更しない。
+    #[inline]
+    pub fn in_file(mut self, file: &str) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.to_owned());
+        }
+        self
+    }
+
+    /// 行番号 (1 始まり) を設定する。すでに設定されている場合は変更しない。
+    #[inline]
+    pub fn at_line(mut self, line: usize) -> Self {
+        if self.line.is_none() {
+            self.line = Some(line);
+        }
+        self
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    #[inline]
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    #[inline]
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}行目: ", file, line)?,
+            (Some(file), None) => write!(f, "{}: ", file)?,
+            (None, Some(line)) => write!(f, "{}行目: ", line)?,
+            (None, None) => (),
+        }
+        match &self.kind {
+            ErrorKind::Io(e) => write!(f, "ファイルの入出力に失敗しました。({})", e),
+            ErrorKind::Serialize(e) => write!(f, "データの保存・復元に失敗しました。({})", e),
+            ErrorKind::Format(message) => write!(f, "{}", message),
+            ErrorKind::Analyze(message) => write!(f, "形態素解析に失敗しました。({})", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            ErrorKind::Serialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        Error::new(ErrorKind::Io(e))
+    }
+}
+
+impl From<bincode::Error> for Error {
+    #[inline]
+    fn from(e: bincode::Error) -> Self {
+        Error::new(ErrorKind::Serialize(e))
+    }
+}
@@ -1,5 +1,6 @@
 use crate::as_chars::AsUsize;
 use crate::double_array::DoubleArray;
+use crate::error::Result;
 use crate::search_cache::{BitCache1, NoCache, SearchCache2};
 use crate::transform_map::{Transform, TransformMap};
 
@@ -8,8 +9,8 @@ pub enum Shrink {}
 impl<K: AsUsize, V, C: SearchCache2> Transform<DoubleArray<K, V, C>, DoubleArray<K, V, NoCache>>
     for Shrink
 {
-    fn transform(src: DoubleArray<K, V, C>) -> DoubleArray<K, V, NoCache> {
-        DoubleArray::from_raw_parts(src.base, src.check, src.data)
+    fn transform(src: DoubleArray<K, V, C>) -> Result<DoubleArray<K, V, NoCache>> {
+        Ok(DoubleArray::from_raw_parts(src.base, src.check, src.data))
     }
 }
 
@@ -0,0 +1,173 @@
+use crate::error::{Error, Result};
+use crate::info::Info;
+use crate::source::split_feature;
+use fnv::FnvHashMap as HashMap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+
+/// 素性文字列（品詞、活用、原形、読み、発音）を重複なく保持する。
+/// `Info::feature` はこの中の位置を指す。
+#[derive(Default, Serialize, Deserialize)]
+pub struct Features {
+    data: Vec<String>,
+    #[serde(skip)]
+    index: HashMap<String, u32>,
+}
+
+impl Features {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// 素性文字列を登録して ID を返す。登録済みの場合は同じ ID を返す。
+    /// 引用符が正しくない場合や、ID が `Info::NO_FEATURE` に達する場合はエラーを返す。
+    pub fn insert(&mut self, feature: &str) -> Result<u32> {
+        if let Some(&id) = self.index.get(feature) {
+            return Ok(id);
+        }
+        Feature::parse(feature)?;
+        if self.data.len() >= Info::NO_FEATURE as usize {
+            return Err(Error::format("素性の数が多すぎます"));
+        }
+        let id = self.data.len() as u32;
+        self.data.push(feature.to_owned());
+        self.index.insert(feature.to_owned(), id);
+        Ok(id)
+    }
+
+    /// 素性文字列は登録するときと読み込むときに確かめているので、列に分けられる。
+    #[inline]
+    pub fn get(&self, id: u32) -> Option<Feature<'_>> {
+        self.data
+            .get(id as usize)
+            .map(|raw| Feature::parse(raw).expect("素性は確認済みです"))
+    }
+
+    pub fn save_to_file<W: Write>(&self, file: W) -> Result<()> {
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(file: &str) -> Result<Self> {
+        let reader = File::open(file).map_err(|e| Error::from(e).in_file(file))?;
+        Features::from_reader(BufReader::new(reader)).map_err(|e| e.in_file(file))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut features: Features = bincode::deserialize_from(reader)?;
+        for (id, feature) in features.data.iter().enumerate() {
+            Feature::parse(feature)?;
+            features.index.insert(feature.clone(), id as u32);
+        }
+        Ok(features)
+    }
+}
+
+/// IPADIC の列順に並んだ素性。存在しない列は `*` になる。
+///
+/// 引用符で囲まれた列の `""` は `"` に戻す。戻す必要のない列は素性文字列を借用する。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Feature<'a> {
+    /// 品詞、品詞細分類1〜3
+    pub pos: [Cow<'a, str>; 4],
+    pub conjugation_type: Cow<'a, str>,
+    pub conjugation_form: Cow<'a, str>,
+    pub base_form: Cow<'a, str>,
+    pub reading: Cow<'a, str>,
+    pub pronunciation: Cow<'a, str>,
+    /// 辞書に書かれていた素性文字列そのもの
+    pub raw: &'a str,
+}
+
+impl<'a> Feature<'a> {
+    /// 素性文字列を列に分ける。引用符で囲まれた列は引用符を除いて返す。
+    /// 引用符が正しくない列があればエラーを返す。
+    pub fn parse(raw: &'a str) -> Result<Self> {
+        let mut row = split_feature(raw);
+        let mut next = || row.next().unwrap_or(Ok(Cow::Borrowed("*")));
+        Ok(Feature {
+            pos: [next()?, next()?, next()?, next()?],
+            conjugation_type: next()?,
+            conjugation_form: next()?,
+            base_form: next()?,
+            reading: next()?,
+            pronunciation: next()?,
+            raw,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup() {
+        let mut features = Features::new();
+        let a = features
+            .insert("名詞,一般,*,*,*,*,すもも,スモモ,スモモ")
+            .unwrap();
+        let b = features.insert("助詞,係助詞,*,*,*,*,も,モ,モ").unwrap();
+        let c = features
+            .insert("名詞,一般,*,*,*,*,すもも,スモモ,スモモ")
+            .unwrap();
+        assert_eq!(a, c);
+        assert_ne!(a, b);
+        assert_eq!(features.len(), 2);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut features = Features::new();
+        let id = features
+            .insert("動詞,自立,*,*,五段・カ行イ音便,基本形,歩く,アルク,アルク")
+            .unwrap();
+        let feature = features.get(id).unwrap();
+        assert_eq!(feature.pos, ["動詞", "自立", "*", "*"]);
+        assert_eq!(feature.conjugation_type, "五段・カ行イ音便");
+        assert_eq!(feature.conjugation_form, "基本形");
+        assert_eq!(feature.base_form, "歩く");
+        assert_eq!(feature.reading, "アルク");
+        assert_eq!(feature.pronunciation, "アルク");
+
+        let feature = Feature::parse("記号,一般").unwrap();
+        assert_eq!(feature.pos, ["記号", "一般", "*", "*"]);
+        assert_eq!(feature.base_form, "*");
+        assert_eq!(features.get(Info::NO_FEATURE), None);
+    }
+
+    #[test]
+    fn test_token() {
+        use crate::{analyze, Matrix, PrefixMapInsert, Trie, Unknown};
+
+        let mut features = Features::new();
+        let mut dic = Trie::<u16, Info>::new();
+        let id = features
+            .insert("名詞,一般,*,*,*,*,すもも,スモモ,スモモ")
+            .unwrap();
+        dic.insert("すもも", Info::with_feature(1, 1, 10, id));
+        let matrix = Matrix {
+            width: 1289,
+            height: 1289,
+            cost: vec![0; 1289 * 1289],
+        };
+        let unknown = Unknown::default();
+        let analyzed = analyze("すもも", &dic, &matrix, &unknown, &features).unwrap();
+        let token = analyzed.iter().next().unwrap();
+        let feature = token.feature.unwrap();
+        assert_eq!(feature.base_form, "すもも");
+        assert_eq!(feature.reading, "スモモ");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::as_chars::CodeUnit;
+
+/// 検索語との編集距離 (レーベンシュタイン距離) を、キーを一単位ずつ伸ばしながら計算する。
+///
+/// 辞書を深さ優先で辿るときに、単位を進めるたびに `push`、戻るたびに `pop` を呼ぶ。
+/// 距離はキーの単位ではなく文字で数える。単位は文字になるまでためておき、
+/// 文字になったところで表を一行積み上げ、戻るときは最後の行を捨てる。
+pub(crate) struct Levenshtein<T> {
+    query: Vec<char>,
+    rows: Vec<usize>,
+    max_distance: usize,
+    /// 今のキーの単位
+    units: Vec<T>,
+    /// 各行の文字が終わる `units` の位置
+    ends: Vec<usize>,
+}
+
+impl<T: CodeUnit> Levenshtein<T> {
+    pub(crate) fn new(query: Vec<T>, max_distance: usize) -> Self {
+        let mut chars = vec![];
+        let mut start = 0;
+        for end in 1..=query.len() {
+            if let Some(ch) = T::decode(&query[start..end]) {
+                chars.push(ch);
+                start = end;
+            }
+        }
+        let rows = (0..=chars.len()).collect();
+        Levenshtein {
+            query: chars,
+            rows,
+            max_distance,
+            units: vec![],
+            ends: vec![],
+        }
+    }
+
+    /// キーに一単位加える。この先のキーで距離が上限に収まる可能性があれば `true` を返す。
+    pub(crate) fn push(&mut self, unit: T) -> bool {
+        let start = self.ends.last().cloned().unwrap_or(0);
+        self.units.push(unit);
+        match T::decode(&self.units[start..]) {
+            Some(ch) => {
+                self.ends.push(self.units.len());
+                self.push_char(ch)
+            }
+            // 文字の途中では距離は変わらない
+            None => true,
+        }
+    }
+
+    fn push_char(&mut self, ch: char) -> bool {
+        let width = self.query.len() + 1;
+        let prev = self.rows.len() - width;
+        let mut min = self.rows[prev] + 1;
+        self.rows.push(min);
+        for j in 1..width {
+            let replace = if self.query[j - 1] == ch { 0 } else { 1 };
+            let distance = (self.rows[prev + j] + 1)
+                .min(self.rows[prev + width + j - 1] + 1)
+                .min(self.rows[prev + j - 1] + replace);
+            self.rows.push(distance);
+            min = min.min(distance);
+        }
+        min <= self.max_distance
+    }
+
+    #[inline]
+    pub(crate) fn pop(&mut self) {
+        if self.ends.last() == Some(&self.units.len()) {
+            self.ends.pop();
+            let len = self.rows.len() - (self.query.len() + 1);
+            self.rows.truncate(len);
+        }
+        self.units.pop();
+    }
+
+    /// 今のキーと検索語の距離。上限を超える場合や、キーが文字の途中で終わる場合は `None` を返す。
+    #[inline]
+    pub(crate) fn distance(&self) -> Option<usize> {
+        if self.ends.last().cloned().unwrap_or(0) != self.units.len() {
+            return None;
+        }
+        let distance = *self.rows.last().unwrap();
+        if distance <= self.max_distance {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance_of<T: CodeUnit>(a: Vec<T>, b: Vec<T>, max_distance: usize) -> Option<usize> {
+        let mut lev = Levenshtein::new(a, max_distance);
+        for unit in b {
+            lev.push(unit);
+        }
+        lev.distance()
+    }
+
+    fn distance(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+        distance_of(a.chars().collect(), b.chars().collect(), max_distance)
+    }
+
+    fn utf8(s: &str) -> Vec<u8> {
+        s.bytes().collect()
+    }
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    // "一文字ずつ伸ばして編集距離を求める"
+    fn test_distance() {
+        assert_eq!(distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("すもも", "すもも", 0), Some(0));
+        assert_eq!(distance("すもも", "もも", 1), Some(1));
+        assert_eq!(distance("", "abc", 5), Some(3));
+    }
+
+    #[test]
+    // "戻ったあとは前の行から計算し直す"
+    fn test_pop() {
+        let mut lev = Levenshtein::new(vec![b'a', b'b'], 1);
+        assert!(lev.push(b'a'));
+        assert!(lev.push(b'x'));
+        assert_eq!(lev.distance(), Some(1));
+        lev.pop();
+        assert!(lev.push(b'b'));
+        assert_eq!(lev.distance(), Some(0));
+        lev.pop();
+        lev.pop();
+        assert!(lev.push(b'x'));
+        assert_eq!(lev.distance(), None);
+        // これより先は距離が上限を超える
+        assert!(!lev.push(b'y'));
+    }
+
+    #[test]
+    // "UTF-8 の多バイト文字は一文字として数える"
+    fn test_utf8() {
+        assert_eq!(distance_of(utf8("すもも"), utf8("すもう"), 1), Some(1));
+        assert_eq!(distance_of(utf8("すもも"), utf8("すもう"), 0), None);
+        assert_eq!(distance_of(utf8("すもも"), utf8("もも"), 1), Some(1));
+        assert_eq!(distance_of(utf8("aすb"), utf8("ab"), 1), Some(1));
+
+        // 文字の途中では距離を返さず、戻れば前の文字から計算し直す
+        let mut lev = Levenshtein::new(utf8("す"), 0);
+        let bytes = utf8("すも");
+        assert!(lev.push(bytes[0]));
+        assert_eq!(lev.distance(), None);
+        assert!(lev.push(bytes[1]));
+        assert!(lev.push(bytes[2]));
+        assert_eq!(lev.distance(), Some(0));
+        assert!(lev.push(bytes[3]));
+        assert_eq!(lev.distance(), None);
+        lev.pop();
+        assert_eq!(lev.distance(), Some(0));
+        lev.pop();
+        assert_eq!(lev.distance(), None);
+    }
+
+    #[test]
+    // "UTF-16 のサロゲートペアは一文字として数える"
+    fn test_utf16() {
+        assert_eq!(distance_of(utf16("𠮷野家"), utf16("吉野家"), 1), Some(1));
+        assert_eq!(distance_of(utf16("𠮷野家"), utf16("野家"), 1), Some(1));
+        assert_eq!(distance_of(utf16("𠮷"), utf16("𩸽"), 1), Some(1));
+        assert_eq!(distance_of(utf16("𠮷"), utf16(""), 0), None);
+
+        let mut lev = Levenshtein::new(utf16("𠮷"), 0);
+        let units = utf16("𠮷");
+        assert!(lev.push(units[0]));
+        assert_eq!(lev.distance(), None);
+        assert!(lev.push(units[1]));
+        assert_eq!(lev.distance(), Some(0));
+    }
+}
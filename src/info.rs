@@ -1,16 +1,32 @@
-#[derive(Copy, Clone, Serialize, Deserialize)]
+/// 単語の文脈IDとコスト、素性の ID。
+///
+/// メモリ上の大きさは 12 バイトで、素性の ID を持たなかったころの 6 バイトの倍になる。
+/// 保存した辞書でも単語ごとに 4 バイト増えるため、それ以前に作った辞書は読み込めない。
+/// メモリマップ形式の辞書でファイルの内容をそのまま `Info` として参照するため、`repr(C)` で配置を固定する。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Info {
     pub left_id: u16,
     pub right_id: u16,
     pub cost: i16,
+    /// `Features` 内の素性の ID
+    pub feature: u32,
 }
 
 impl Info {
+    /// 素性を持たないことを表す ID
+    pub const NO_FEATURE: u32 = u32::MAX;
+
     pub fn new(left_id: u16, right_id: u16, cost: i16) -> Self {
+        Info::with_feature(left_id, right_id, cost, Info::NO_FEATURE)
+    }
+
+    pub fn with_feature(left_id: u16, right_id: u16, cost: i16, feature: u32) -> Self {
         Info {
             left_id,
             right_id,
             cost,
+            feature,
         }
     }
 }
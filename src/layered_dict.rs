@@ -0,0 +1,440 @@
+use crate::as_chars::{AsChars, CodeUnit};
+use crate::info::Info;
+use crate::prefix_map::{PrefixMap, PrefixMapInsert, PrefixMapMut};
+use crate::trie::Trie;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// システム辞書の上にユーザー辞書を重ねた辞書。
+///
+/// 重ねた辞書の単語は、同じ表層形のシステム辞書の単語とまとめてトライ木に保持する。
+/// 検索ではトライ木にある表層形を優先し、ない場合はシステム辞書の結果を使う。
+/// ユーザー辞書は小さい前提で、システム辞書はコピーしない。
+pub struct LayeredDict<K, D> {
+    base: D,
+    overlay: Trie<K, Info>,
+    /// 表層形ごとの、トライ木の先頭にある引き継いだシステム辞書の単語の数
+    inherited: BTreeMap<Vec<K>, usize>,
+    /// 重ねた単語の数
+    added: usize,
+    phantom: PhantomData<K>,
+}
+
+impl<K: Copy + Ord, D: PrefixMap<K, Info>> LayeredDict<K, D> {
+    #[inline]
+    pub fn new(base: D) -> Self {
+        LayeredDict {
+            base,
+            overlay: Trie::new(),
+            inherited: BTreeMap::new(),
+            added: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn base(&self) -> &D {
+        &self.base
+    }
+
+    /// 辞書を一つ重ねる。重ねる辞書の単語のコストには `cost_offset` を加える。
+    pub fn add_layer<U: PrefixMap<K, Info>>(&mut self, layer: &U, cost_offset: i16) {
+        layer.each_entry(|key, infos| {
+            for info in infos {
+                let mut info = *info;
+                info.cost = info.cost.saturating_add(cost_offset);
+                self.insert(key, info);
+            }
+        });
+    }
+}
+
+impl<K: Copy + Ord, D: PrefixMap<K, Info>> PrefixMap<K, Info> for LayeredDict<K, D> {
+    #[inline]
+    fn count(&self) -> usize {
+        self.base.count() + self.added
+    }
+
+    #[inline]
+    fn get<T: AsChars<K>>(&self, key: T) -> Option<&[Info]> {
+        self.overlay.get(ByRef(&key)).or_else(|| self.base.get(key))
+    }
+
+    /// 同じ長さなら重ねた辞書の結果を返す。
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[Info])> {
+        let overlay = self.overlay.longest_match(ByRef(&key));
+        match (overlay, self.base.longest_match(key)) {
+            (Some(overlay), Some(base)) if base.0 > overlay.0 => Some(base),
+            (None, base) => base,
+            (overlay, _) => overlay,
+        }
+    }
+
+    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[Info])>(&self, key: T, mut f: F) {
+        // 重ねた辞書の結果は先にまとめておく
+        let mut overlay = vec![];
+        let mut node = &self.overlay.root;
+        for (ix, ch) in key.as_chars().enumerate() {
+            match node.children.binary_search_by_key(&ch, |&(c, _)| c) {
+                Ok(child) => node = &node.children[child].1,
+                Err(_) => break,
+            }
+            if !node.data.is_empty() {
+                overlay.push((ix + 1, &node.data[..]));
+            }
+        }
+
+        // 長さの順に、重ねた辞書にある長さではその結果を、ない長さではシステム辞書の結果を渡す
+        let mut overlay = overlay.into_iter().peekable();
+        self.base.each_prefix(key, |len, infos| {
+            while let Some(&(overlay_len, overlay_infos)) = overlay.peek() {
+                if overlay_len > len {
+                    break;
+                }
+                f(overlay_len, overlay_infos);
+                overlay.next();
+                if overlay_len == len {
+                    return;
+                }
+            }
+            f(len, infos);
+        });
+        for (len, infos) in overlay {
+            f(len, infos);
+        }
+    }
+
+    #[inline]
+    fn each_entry<'a, F: FnMut(&[K], &'a [Info])>(&'a self, mut f: F) {
+        self.each_predictive(&[][..], |key, infos| {
+            f(key, infos);
+            true
+        });
+    }
+
+    /// 重ねた辞書とシステム辞書のキーを辞書順にまとめて渡す。
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [Info]) -> bool>(
+        &'a self,
+        prefix: T,
+        f: F,
+    ) {
+        let overlay = self.overlay.predict(ByRef(&prefix), usize::MAX);
+        let walk = |g: &mut Visit<K, &'a [Info]>| {
+            self.base
+                .each_predictive(prefix, |key, infos| g(key, infos))
+        };
+        merge_sorted(overlay, walk, f);
+    }
+
+    /// 重ねた辞書とシステム辞書のキーを辞書順にまとめて渡す。
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [Info]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+    {
+        let overlay = self
+            .overlay
+            .fuzzy(ByRef(&key), max_distance, usize::MAX)
+            .into_iter()
+            .map(|(key, distance, infos)| (key, (distance, infos)))
+            .collect();
+        let walk = |g: &mut Visit<K, (usize, &'a [Info])>| {
+            self.base
+                .each_fuzzy(key, max_distance, |key, distance, infos| {
+                    g(key, (distance, infos))
+                })
+        };
+        merge_sorted(overlay, walk, |key, (distance, infos)| {
+            f(key, distance, infos)
+        });
+    }
+}
+
+impl<K: Copy + Ord, D: PrefixMap<K, Info>> PrefixMapInsert<K, Info> for LayeredDict<K, D> {
+    /// 単語を重ねる。システム辞書にある表層形なら、その単語も引き継ぐ。
+    fn insert<T: AsChars<K>>(&mut self, key: T, value: Info) {
+        let key: Vec<K> = key.as_chars().collect();
+        if self.overlay.get(&key[..]).is_none() {
+            if let Some(infos) = self.base.get(&key[..]) {
+                for info in infos {
+                    self.overlay.insert(&key[..], *info);
+                }
+                self.inherited.insert(key.clone(), infos.len());
+            }
+        }
+        self.overlay.insert(&key[..], value);
+        self.added += 1;
+    }
+}
+
+impl<K: Copy + Ord, D: PrefixMapMut<K, Info>> PrefixMapMut<K, Info> for LayeredDict<K, D> {
+    /// 重ねた辞書とシステム辞書の両方から取り除く。
+    /// システム辞書にある表層形を取り除く場合は、システム辞書が変更できる必要がある。
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<Info>> {
+        let key: Vec<K> = key.as_chars().collect();
+        let base = self.base.remove(&key[..]);
+        match self.overlay.remove(&key[..]) {
+            Some(infos) => {
+                let inherited = self.inherited.remove(&key).unwrap_or(0);
+                let user = infos.len().saturating_sub(inherited);
+                self.added = self.added.saturating_sub(user);
+                Some(infos)
+            }
+            None => base,
+        }
+    }
+
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &Info) -> bool {
+        let key: Vec<K> = key.as_chars().collect();
+        let ix = match self.overlay.get(&key[..]) {
+            Some(infos) => infos.iter().position(|info| info == value),
+            None => return self.base.remove_value(&key[..], value),
+        };
+        let ix = match ix {
+            Some(ix) => ix,
+            None => return false,
+        };
+        self.overlay.remove_value(&key[..], value);
+        match self.inherited.get_mut(&key) {
+            Some(inherited) if ix < *inherited => {
+                *inherited -= 1;
+                self.base.remove_value(&key[..], value);
+            }
+            _ => self.added = self.added.saturating_sub(1),
+        }
+        true
+    }
+}
+
+/// `merge_sorted` でシステム辞書の結果を受け取る関数
+type Visit<'v, K, E> = dyn FnMut(&[K], E) -> bool + 'v;
+
+/// 辞書順に並んだ重ねた辞書の結果と、`walk` で辿るシステム辞書の結果を辞書順にまとめて `f` に渡す。
+/// 同じキーでは重ねた辞書の結果を使う。`f` が `false` を返すと打ち切る。
+fn merge_sorted<K: Ord, E: Copy, W, F>(overlay: Vec<(Vec<K>, E)>, walk: W, mut f: F)
+where
+    W: FnOnce(&mut Visit<K, E>),
+    F: FnMut(&[K], E) -> bool,
+{
+    let mut overlay = overlay.into_iter().peekable();
+    let mut done = false;
+    walk(&mut |key, value| {
+        while let Some((overlay_key, overlay_value)) = overlay.peek() {
+            if &overlay_key[..] > key {
+                break;
+            }
+            let same = &overlay_key[..] == key;
+            done = !f(overlay_key, *overlay_value);
+            overlay.next();
+            if done || same {
+                return !done;
+            }
+        }
+        done = !f(key, value);
+        !done
+    });
+    if done {
+        return;
+    }
+    for (key, value) in overlay {
+        if !f(&key, value) {
+            return;
+        }
+    }
+}
+
+/// 同じキーで二つの辞書を検索するため、キーを借用して渡す
+struct ByRef<'a, T>(&'a T);
+
+impl<'a, K, T: AsChars<K>> AsChars<K> for ByRef<'a, T> {
+    type I = T::I;
+
+    #[inline]
+    fn as_chars(&self) -> Self::I {
+        self.0.as_chars()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefix_map::owned_keys;
+
+    fn base() -> Trie<u8, Info> {
+        let mut dic = Trie::new();
+        dic.insert("a", Info::new(1, 1, 10));
+        dic.insert("abc", Info::new(1, 1, 30));
+        dic
+    }
+
+    fn costs(infos: &[Info]) -> Vec<i16> {
+        infos.iter().map(|info| info.cost).collect()
+    }
+
+    #[test]
+    // "重ねた辞書とシステム辞書の結果をまとめる"
+    fn test_merge() {
+        let mut user = Trie::new();
+        user.insert("ab", Info::new(2, 2, 20));
+        user.insert("abc", Info::new(2, 2, 31));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user, -5);
+
+        assert_eq!(dic.count(), 4);
+        assert_eq!(costs(dic.get("a").unwrap()), vec![10]);
+        assert_eq!(costs(dic.get("ab").unwrap()), vec![15]);
+        assert_eq!(costs(dic.get("abc").unwrap()), vec![30, 26]);
+        assert!(dic.get("b").is_none());
+
+        let keys: Vec<_> = dic
+            .entries()
+            .into_iter()
+            .map(|(key, infos)| (String::from_utf8(key).unwrap(), costs(infos)))
+            .collect();
+        let expected = owned_keys(vec![
+            ("a", vec![10]),
+            ("ab", vec![15]),
+            ("abc", vec![30, 26]),
+        ]);
+        assert_eq!(keys, expected);
+
+        let (len, infos) = dic.longest_match("abcd").unwrap();
+        assert_eq!((len, costs(infos)), (3, vec![30, 26]));
+        let (len, infos) = dic.longest_match("abx").unwrap();
+        assert_eq!((len, costs(infos)), (2, vec![15]));
+        let (len, infos) = dic.longest_match("ax").unwrap();
+        assert_eq!((len, costs(infos)), (1, vec![10]));
+
+        let mut prefixes = vec![];
+        dic.each_prefix("abcd", |len, infos| prefixes.push((len, costs(infos))));
+        assert_eq!(
+            prefixes,
+            vec![(1, vec![10]), (2, vec![15]), (3, vec![30, 26])]
+        );
+    }
+
+    #[test]
+    // "前方一致の列挙でも重ねた辞書を優先する"
+    fn test_predictive() {
+        let mut user = Trie::new();
+        user.insert("ab", Info::new(2, 2, 20));
+        user.insert("abc", Info::new(2, 2, 31));
+        user.insert("b", Info::new(2, 2, 40));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user, 0);
+
+        let found: Vec<_> = dic
+            .predict("ab", 10)
+            .into_iter()
+            .map(|(key, infos)| (key, costs(infos)))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(b"ab".to_vec(), vec![20]), (b"abc".to_vec(), vec![30, 31])]
+        );
+        assert_eq!(dic.predict("", 10).len(), 4);
+        assert_eq!(dic.predict("", 3).len(), 3);
+    }
+
+    #[test]
+    // "編集距離の検索でも重ねた辞書を優先する"
+    fn test_fuzzy() {
+        let mut user = Trie::new();
+        user.insert("abc", Info::new(2, 2, 31));
+        user.insert("abd", Info::new(2, 2, 40));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user, 0);
+
+        let found: Vec<_> = dic
+            .fuzzy("abc", 1, 10)
+            .into_iter()
+            .map(|(key, distance, infos)| (key, distance, costs(infos)))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (b"abc".to_vec(), 0, vec![30, 31]),
+                (b"abd".to_vec(), 1, vec![40])
+            ]
+        );
+        assert_eq!(dic.fuzzy("abc", 2, 10).len(), 3);
+        assert_eq!(dic.fuzzy("abc", 2, 1).len(), 1);
+    }
+
+    #[test]
+    // "取り除いた単語は両方の辞書から消える"
+    fn test_remove() {
+        let mut user = Trie::new();
+        user.insert("ab", Info::new(2, 2, 20));
+        user.insert("abc", Info::new(2, 2, 31));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user, 0);
+        assert_eq!(dic.count(), 4);
+
+        assert!(dic.remove_value("abc", &Info::new(2, 2, 31)));
+        assert_eq!(costs(dic.get("abc").unwrap()), vec![30]);
+        assert_eq!(dic.count(), 3);
+        assert!(dic.remove_value("abc", &Info::new(1, 1, 30)));
+        assert!(dic.get("abc").is_none());
+        assert!(dic.base().get("abc").is_none());
+        assert_eq!(dic.count(), 2);
+
+        assert_eq!(costs(&dic.remove("ab").unwrap()), vec![20]);
+        assert_eq!(costs(&dic.remove("a").unwrap()), vec![10]);
+        assert_eq!(dic.count(), 0);
+        assert!(dic.remove("a").is_none());
+    }
+
+    #[test]
+    // "システム辞書の単語を取り除いたあとに重ねても単語の数が合う"
+    fn test_remove_after_base_change() {
+        let mut user = Trie::new();
+        user.insert("abc", Info::new(2, 2, 31));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user, 0);
+        assert!(dic.remove_value("abc", &Info::new(1, 1, 30)));
+        assert!(dic.base().get("abc").is_none());
+        assert_eq!(dic.count(), 2);
+
+        let mut user = Trie::new();
+        user.insert("abc", Info::new(3, 3, 32));
+        dic.add_layer(&user, 0);
+        assert_eq!(dic.count(), 3);
+        assert_eq!(costs(&dic.remove("abc").unwrap()), vec![31, 32]);
+        assert_eq!(dic.count(), 1);
+
+        // システム辞書と同じ単語を重ねる
+        dic.insert("a", Info::new(1, 1, 10));
+        assert_eq!(dic.count(), 2);
+        assert!(dic.remove_value("a", &Info::new(1, 1, 10)));
+        assert!(dic.base().get("a").is_none());
+        assert_eq!(dic.count(), 1);
+        assert!(dic.remove_value("a", &Info::new(1, 1, 10)));
+        assert_eq!(dic.count(), 0);
+        assert!(dic.remove("a").is_none());
+    }
+
+    #[test]
+    // "複数の辞書を重ねられる"
+    fn test_layers() {
+        let mut user1 = Trie::new();
+        user1.insert("abcd", Info::new(2, 2, 40));
+        let mut user2 = Trie::new();
+        user2.insert("abcd", Info::new(3, 3, 50));
+        user2.insert("x", Info::new(3, 3, 60));
+        let mut dic = LayeredDict::new(base());
+        dic.add_layer(&user1, 0);
+        dic.add_layer(&user2, 100);
+
+        assert_eq!(costs(dic.get("abcd").unwrap()), vec![40, 150]);
+        let mut prefixes = vec![];
+        dic.each_prefix("abcde", |len, infos| prefixes.push((len, costs(infos))));
+        assert_eq!(
+            prefixes,
+            vec![(1, vec![10]), (3, vec![30]), (4, vec![40, 150])]
+        );
+    }
+}
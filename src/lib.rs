@@ -3,27 +3,48 @@ extern crate serde_derive;
 
 mod analyze;
 mod as_chars;
+mod bundle;
+mod charset;
 mod dictionary;
 mod double_array;
+mod error;
 mod fast_build_double_array;
+mod feature;
+mod fuzzy;
 mod info;
+mod layered_dict;
+mod mapped_double_array;
 mod matrix;
 mod prefix_map;
 mod recursive_hash_map;
 mod search_cache;
+mod source;
 mod transform_map;
 mod trie;
 mod trie_to_double_array;
+mod unknown;
 
-pub use crate::analyze::analyze;
-pub use crate::as_chars::{AsChars, IntoString};
-pub use crate::dictionary::{LoadDict, SaveDict};
+pub use crate::analyze::{
+    analyze, analyze_constrained, Analyzed, Constraints, Lattice, LatticeEdge, LatticeNode,
+    Marginal, Marginals, NBest, Path, PathIter, Token,
+};
+pub use crate::as_chars::{AsChars, CodeUnit, IntoString};
+pub use crate::bundle::{BundleHeader, Metadata, Tokenizer};
+pub use crate::charset::Charset;
+pub use crate::dictionary::{DictHeader, DictKind, DictType, DictValue, LoadDict, SaveDict};
+pub use crate::double_array::Defect;
+pub use crate::error::{Error, ErrorKind};
 pub use crate::fast_build_double_array::FastBuildDoubleArray;
+pub use crate::feature::{Feature, Features};
 pub use crate::info::Info;
+pub use crate::layered_dict::LayeredDict;
+pub use crate::mapped_double_array::{MappedDoubleArray, Trie2MappedMap};
 pub use crate::matrix::Matrix;
-pub use crate::prefix_map::PrefixMap;
+pub use crate::prefix_map::{PrefixMap, PrefixMapInsert, PrefixMapMut};
 pub use crate::recursive_hash_map::RecursiveHashMap;
+pub use crate::source::{Entry, SourceFormat, SourceReader};
 pub use crate::trie::Trie;
 pub use crate::trie_to_double_array::Trie2DAMap;
+pub use crate::unknown::{CharCategory, Unknown};
 
 pub type DoubleArray<K, V> = double_array::DoubleArray<K, V, search_cache::NoCache>;
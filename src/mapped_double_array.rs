@@ -0,0 +1,495 @@
+use crate::as_chars::{AsChars, AsUsize, CodeUnit};
+use crate::dictionary::{LoadDict, SaveDict};
+use crate::double_array::{self, Children, DoubleArray};
+use crate::error::{Error, Result};
+use crate::fuzzy::Levenshtein;
+use crate::info::Info;
+use crate::prefix_map::PrefixMap;
+use crate::transform_map::{Transform, TransformMap};
+use crate::trie::Trie;
+use crate::trie_to_double_array;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::{mem, slice};
+
+// ファイルの形式。数値はすべてリトルエンディアンで、4 バイト単位で並べる。
+//
+// | 位置 (4 バイト単位) | 内容                                                       |
+// |---------------------|------------------------------------------------------------|
+// | 0                   | 識別子 `DMDA`                                              |
+// | 1                   | バージョン                                                 |
+// | 2                   | キー一文字のバイト数 (u8 は 1、u16 は 2、char は 4)        |
+// | 3                   | 値一つのバイト数 (`Info` は 12)                            |
+// | 4                   | 配列の長さ n                                               |
+// | 5                   | 値の数 m                                                   |
+// | 6                   | base (n 個)                                                |
+// | 6 + n               | check (n 個)                                               |
+// | 6 + 2n              | 各位置の値の開始位置 (n + 1 個)。位置 i の値は i 番目から i + 1 番目の手前まで |
+// | 7 + 3n              | 値 (m 個)。`Info` を left_id (u16)、right_id (u16)、cost (i16)、0 埋め (2 バイト)、feature (u32) の順に並べる |
+
+pub(crate) const MAGIC: &[u8; 4] = b"DMDA";
+const VERSION: u32 = 1;
+const HEADER_WORDS: usize = 6;
+const INFO_WORDS: usize = mem::size_of::<Info>() / 4;
+
+// 値をファイルからそのまま参照するため、`Info` の大きさと配置を固定しておく
+const _: () = assert!(mem::size_of::<Info>() == 12 && mem::align_of::<Info>() == 4);
+
+/// 値をリトルエンディアンのまま参照するため、ビッグエンディアンの環境ではエラーにする。
+fn check_endian() -> Result<()> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::format(
+            "ビッグエンディアンの環境には対応していません",
+        ));
+    }
+    Ok(())
+}
+
+enum Storage {
+    Mapped(Mmap),
+    Owned(Vec<u32>),
+}
+
+/// 読み込み専用のダブル配列。
+///
+/// ファイルをメモリマップして、base、check、値をコピーせずに検索に使う。
+/// 読み込みはほぼ一瞬で終わり、同じファイルを開いたプロセス間でメモリが共有される。
+/// リトルエンディアンの環境でのみ使える。
+pub struct MappedDoubleArray<K> {
+    storage: Storage,
+    len: usize,
+    values_len: usize,
+    phantom: PhantomData<K>,
+}
+
+impl<K> MappedDoubleArray<K> {
+    #[inline]
+    fn words(&self) -> &[u32] {
+        match &self.storage {
+            // 読み込み時に 4 バイト境界に揃っていることを確かめている
+            Storage::Mapped(mmap) => unsafe {
+                slice::from_raw_parts(mmap.as_ptr() as *const u32, mmap.len() / 4)
+            },
+            Storage::Owned(words) => words,
+        }
+    }
+
+    #[inline]
+    fn base(&self) -> &[u32] {
+        &self.words()[HEADER_WORDS..HEADER_WORDS + self.len]
+    }
+
+    #[inline]
+    fn check(&self) -> &[u32] {
+        let start = HEADER_WORDS + self.len;
+        &self.words()[start..start + self.len]
+    }
+
+    #[inline]
+    fn offsets(&self) -> &[u32] {
+        let start = HEADER_WORDS + self.len * 2;
+        &self.words()[start..=start + self.len]
+    }
+
+    #[inline]
+    fn values(&self) -> &[Info] {
+        let start = HEADER_WORDS + self.len * 3 + 1;
+        let words = &self.words()[start..start + self.values_len * INFO_WORDS];
+        // `Info` は整数だけからなり、大きさと配置は上で確かめている
+        unsafe { slice::from_raw_parts(words.as_ptr() as *const Info, self.values_len) }
+    }
+
+    /// 位置 `ix` の値。壊れたファイルでも範囲外を参照しないようにする。
+    #[inline]
+    fn values_at(&self, ix: usize) -> &[Info] {
+        let offsets = self.offsets();
+        match (offsets.get(ix), offsets.get(ix + 1)) {
+            (Some(&start), Some(&end)) => self
+                .values()
+                .get(start as usize..end as usize)
+                .unwrap_or(&[]),
+            _ => &[],
+        }
+    }
+
+    /// ヘッダを確かめて、配列の長さと値の数を返す。
+    fn parse_header(words: &[u32]) -> Result<(usize, usize)> {
+        check_endian()?;
+        if words.len() < HEADER_WORDS || words[0].to_le_bytes() != *MAGIC {
+            return Err(Error::format("メモリマップ形式の辞書ではありません"));
+        }
+        if words[1] != VERSION {
+            let message = format!("対応していないバージョンです ({})", words[1]);
+            return Err(Error::format(message));
+        }
+        if words[2] as usize != mem::size_of::<K>() || words[3] as usize != mem::size_of::<Info>() {
+            return Err(Error::format("辞書のタイプが一致しません"));
+        }
+        let (len, values_len) = (words[4] as usize, words[5] as usize);
+        if words.len() != HEADER_WORDS + len * 3 + 1 + values_len * INFO_WORDS {
+            return Err(Error::format("辞書の大きさが正しくありません"));
+        }
+        Ok((len, values_len))
+    }
+
+    /// バイト列から読み込む。内容はコピーして保持する。
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(Error::format("辞書の大きさが正しくありません"));
+        }
+        let words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let (len, values_len) = Self::parse_header(&words)?;
+        Ok(MappedDoubleArray {
+            storage: Storage::Owned(words),
+            len,
+            values_len,
+            phantom: PhantomData,
+        })
+    }
+
+    /// ファイルをメモリマップして読み込む。
+    ///
+    /// 読み込んだ後にファイルが書き換えられた場合の動作は保証しない。
+    pub fn load_mapped(file: &str) -> Result<Self> {
+        let load = || {
+            let mmap = unsafe { Mmap::map(&File::open(file)?)? };
+            if !mmap.len().is_multiple_of(4)
+                || !(mmap.as_ptr() as usize).is_multiple_of(mem::align_of::<u32>())
+            {
+                return Err(Error::format("辞書の大きさが正しくありません"));
+            }
+            let words =
+                unsafe { slice::from_raw_parts(mmap.as_ptr() as *const u32, mmap.len() / 4) };
+            let (len, values_len) = Self::parse_header(words)?;
+            Ok(MappedDoubleArray {
+                storage: Storage::Mapped(mmap),
+                len,
+                values_len,
+                phantom: PhantomData,
+            })
+        };
+        load().map_err(|e: Error| e.in_file(file))
+    }
+}
+
+impl<K: AsUsize> MappedDoubleArray<K> {
+    /// ダブル配列をこの形式に変換する。
+    pub fn from_double_array<C>(da: &DoubleArray<K, Info, C>) -> Result<Self> {
+        check_endian()?;
+        let len = da.base.len();
+        let values_len: usize = da.data.iter().map(Vec::len).sum();
+        let mut words = Vec::with_capacity(HEADER_WORDS + len * 3 + 1 + values_len * INFO_WORDS);
+        words.push(u32::from_le_bytes(*MAGIC));
+        words.push(VERSION);
+        words.push(mem::size_of::<K>() as u32);
+        words.push(mem::size_of::<Info>() as u32);
+        words.push(len as u32);
+        words.push(values_len as u32);
+        words.extend_from_slice(&da.base);
+        words.extend_from_slice(&da.check);
+        let mut offset = 0;
+        words.push(offset);
+        for values in &da.data {
+            offset += values.len() as u32;
+            words.push(offset);
+        }
+        for info in da.data.iter().flatten() {
+            words.push(info.left_id as u32 | (info.right_id as u32) << 16);
+            words.push(info.cost as u16 as u32);
+            words.push(info.feature);
+        }
+        Ok(MappedDoubleArray {
+            storage: Storage::Owned(words),
+            len,
+            values_len,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn next_index(&self, current_ix: usize, ch: K) -> Option<usize> {
+        let base = *self.base().get(current_ix)? as usize;
+        if base == 0 {
+            return None;
+        }
+        let next_ix = base + ch.as_usize();
+        if *self.check().get(next_ix)? as usize == current_ix {
+            Some(next_ix)
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: AsUsize> PrefixMap<K, Info> for MappedDoubleArray<K> {
+    #[inline]
+    fn count(&self) -> usize {
+        self.values_len
+    }
+
+    fn get<T: AsChars<K>>(&self, key: T) -> Option<&[Info]> {
+        let mut current_ix = 1;
+        for ch in key.as_chars() {
+            current_ix = self.next_index(current_ix, ch)?;
+        }
+        let values = self.values_at(current_ix);
+        if values.is_empty() {
+            None
+        } else {
+            Some(values)
+        }
+    }
+    #[inline]
+    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[Info])>(&self, key: T, mut f: F) {
+        let mut current_ix = 1;
+        for (ix, ch) in key.as_chars().enumerate() {
+            match self.next_index(current_ix, ch) {
+                Some(next_ix) => current_ix = next_ix,
+                None => return,
+            }
+            let values = self.values_at(current_ix);
+            if !values.is_empty() {
+                f(ix + 1, values);
+            }
+        }
+    }
+
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[Info])> {
+        let mut current_ix = 1;
+        let mut longest = None;
+        for (ix, ch) in key.as_chars().enumerate() {
+            match self.next_index(current_ix, ch) {
+                Some(next_ix) => current_ix = next_ix,
+                None => break,
+            }
+            let values = self.values_at(current_ix);
+            if !values.is_empty() {
+                longest = Some((ix + 1, values));
+            }
+        }
+        longest
+    }
+
+    fn each_entry<'a, F: FnMut(&[K], &'a [Info])>(&'a self, mut f: F) {
+        double_array::each_node(self.base(), self.check(), |key, ix| {
+            let values = self.values_at(ix);
+            if !values.is_empty() {
+                f(key, values);
+            }
+        });
+    }
+
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [Info]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+    {
+        let mut lev = Levenshtein::new(key.as_chars().collect(), max_distance);
+        let (base, check) = (self.base(), self.check());
+        let mut visit = |key: &[K], ix: usize, distance| {
+            let values = self.values_at(ix);
+            values.is_empty() || f(key, distance, values)
+        };
+        let children = Children::new(base, check);
+        double_array::each_fuzzy(base, &children, 1, &mut vec![], &mut lev, &mut visit);
+    }
+
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [Info]) -> bool>(
+        &'a self,
+        prefix: T,
+        mut f: F,
+    ) {
+        let mut key = vec![];
+        let mut current_ix = 1;
+        for ch in prefix.as_chars() {
+            match self.next_index(current_ix, ch) {
+                Some(next_ix) => current_ix = next_ix,
+                None => return,
+            }
+            key.push(ch);
+        }
+        let (base, check) = (self.base(), self.check());
+        let children = Children::new(base, check);
+        double_array::each_descendant(base, &children, current_ix, &mut key, &mut |key, ix| {
+            let values = self.values_at(ix);
+            values.is_empty() || f(key, values)
+        });
+    }
+}
+
+impl<K: AsUsize> SaveDict<K, Info> for MappedDoubleArray<K> {
+    fn save_to_file<W: Write>(self, mut file: W) -> Result<Self> {
+        let bytes: Vec<u8> = self.words().iter().flat_map(|w| w.to_le_bytes()).collect();
+        file.write_all(&bytes)?;
+        file.flush()?;
+        Ok(self)
+    }
+}
+
+impl<K: AsUsize> LoadDict<K, Info> for MappedDoubleArray<K> {
+    /// 内容をコピーして読み込む。ファイルから読み込む場合はメモリマップする。
+    fn load_from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        MappedDoubleArray::from_bytes(&bytes)
+    }
+
+    #[inline]
+    fn load_from_file(file: &str) -> Result<Self> {
+        MappedDoubleArray::load_mapped(file)
+    }
+}
+
+pub enum Trie2Mapped {}
+
+impl<K: AsUsize> Transform<Trie<K, Info>, MappedDoubleArray<K>> for Trie2Mapped {
+    fn transform(trie: Trie<K, Info>) -> Result<MappedDoubleArray<K>> {
+        MappedDoubleArray::from_double_array(&trie_to_double_array::transform(trie))
+    }
+}
+
+/// 単語を登録したトライ木から作り、メモリマップ形式のダブル配列として保存する。
+pub type Trie2MappedMap<K> = TransformMap<Trie<K, Info>, MappedDoubleArray<K>, Trie2Mapped>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search_cache::NoCache;
+
+    fn double_array() -> DoubleArray<u16, Info, NoCache> {
+        let mut da = DoubleArray::new();
+        da.insert("すもも", Info::with_feature(1, 2, -3, 4));
+        da.insert("すもも", Info::new(5, 6, 7));
+        da.insert("もも", Info::new(8, 9, 10));
+        da.insert("す", Info::new(11, 12, 13));
+        da
+    }
+
+    fn costs(values: Option<&[Info]>) -> Vec<i16> {
+        values.unwrap_or(&[]).iter().map(|info| info.cost).collect()
+    }
+
+    #[test]
+    // "元のダブル配列と同じ検索結果になる"
+    fn test_same_result() {
+        let da = double_array();
+        let mapped = MappedDoubleArray::from_double_array(&da).unwrap();
+        assert_eq!(mapped.count(), 4);
+        for key in &["すもも", "もも", "す", "すも", "も", "あ", ""] {
+            assert_eq!(costs(mapped.get(*key)), costs(da.get(*key)));
+        }
+        let info = mapped.get("すもも").unwrap()[0];
+        assert_eq!(
+            (info.left_id, info.right_id, info.cost, info.feature),
+            (1, 2, -3, 4)
+        );
+
+        let mut prefixes = vec![];
+        mapped.each_prefix("すももも", |len, values| {
+            prefixes.push((len, costs(Some(values))))
+        });
+        assert_eq!(prefixes, vec![(1, vec![13]), (3, vec![-3, 7])]);
+
+        let longest = mapped.longest_match("すももも").unwrap();
+        assert_eq!((longest.0, costs(Some(longest.1))), (3, vec![-3, 7]));
+        assert!(mapped.longest_match("あ").is_none());
+    }
+
+    #[test]
+    // "元のダブル配列と同じキーを列挙する"
+    fn test_each_entry() {
+        let da = double_array();
+        let mapped = MappedDoubleArray::from_double_array(&da).unwrap();
+        let mut expected = vec![];
+        da.each_entry(|key, values| expected.push((key.to_owned(), costs(Some(values)))));
+        let mut entries = vec![];
+        mapped.each_entry(|key, values| entries.push((key.to_owned(), costs(Some(values)))));
+        assert_eq!(entries, expected);
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    // "元のダブル配列と同じキーを前方一致で列挙する"
+    fn test_predictive() {
+        let da = double_array();
+        let mapped = MappedDoubleArray::from_double_array(&da).unwrap();
+        for prefix in &["", "す", "すも", "も", "ま"] {
+            let expected: Vec<_> = da
+                .predict(*prefix, 10)
+                .into_iter()
+                .map(|(key, values)| (key, costs(Some(values))))
+                .collect();
+            let entries: Vec<_> = mapped
+                .predict(*prefix, 10)
+                .into_iter()
+                .map(|(key, values)| (key, costs(Some(values))))
+                .collect();
+            assert_eq!(entries, expected);
+        }
+        assert_eq!(mapped.predict("す", 10).len(), 2);
+        assert_eq!(mapped.predict("す", 1).len(), 1);
+    }
+
+    #[test]
+    // "元のダブル配列と同じキーを編集距離で検索する"
+    fn test_fuzzy() {
+        let da = double_array();
+        let mapped = MappedDoubleArray::from_double_array(&da).unwrap();
+        for max_distance in 0..3 {
+            let expected: Vec<_> = da
+                .fuzzy("すもう", max_distance, 10)
+                .into_iter()
+                .map(|(key, distance, values)| (key, distance, costs(Some(values))))
+                .collect();
+            let found: Vec<_> = mapped
+                .fuzzy("すもう", max_distance, 10)
+                .into_iter()
+                .map(|(key, distance, values)| (key, distance, costs(Some(values))))
+                .collect();
+            assert_eq!(found, expected);
+        }
+        assert_eq!(mapped.fuzzy("すもう", 1, 10).len(), 1);
+        assert_eq!(mapped.fuzzy("すもう", 2, 10).len(), 3);
+    }
+
+    #[test]
+    // "保存したバイト列から読み込める"
+    fn test_save_and_load() {
+        let mapped = MappedDoubleArray::from_double_array(&double_array()).unwrap();
+        let mut bytes = vec![];
+        mapped.save_to_file(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let loaded = MappedDoubleArray::<u16>::from_bytes(&bytes).unwrap();
+        assert_eq!(costs(loaded.get("もも")), vec![10]);
+
+        // キーの種類や大きさが合わない場合はエラーになる
+        assert!(MappedDoubleArray::<u8>::from_bytes(&bytes).is_err());
+        assert!(MappedDoubleArray::<u16>::from_bytes(&bytes[..bytes.len() - 4]).is_err());
+        assert!(MappedDoubleArray::<u16>::from_bytes(b"DMTX").is_err());
+    }
+
+    #[test]
+    // "ファイルをメモリマップして読み込む"
+    fn test_load_mapped() {
+        let path = std::env::temp_dir().join(format!("dotamoji-mapped-{}.dic", std::process::id()));
+        let path = path.to_str().unwrap();
+        let file = File::create(path).unwrap();
+        MappedDoubleArray::from_double_array(&double_array())
+            .unwrap()
+            .save_to_file(file)
+            .unwrap();
+        let loaded = MappedDoubleArray::<u16>::load_from_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(costs(loaded.get("すもも")), vec![-3, 7]);
+        assert!(MappedDoubleArray::<u16>::load_from_file(path).is_err());
+    }
+}
@@ -1,15 +1,49 @@
+use crate::charset::Charset;
+use crate::error::{Error, Result};
+use crate::info::Info;
+use crate::prefix_map::PrefixMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::str::FromStr;
 
+/// バイナリ形式の先頭に置く識別子
+const MAGIC: &[u8; 4] = b"DMTX";
+/// バイナリ形式のバージョン
+const VERSION: u32 = 1;
+
 #[inline]
-fn read_int<T: FromStr>(str: &str) -> T {
+fn read_int<T: FromStr>(str: &str) -> Result<T> {
     match str.trim().parse() {
-        Ok(x) => x,
-        Err(_) => panic!("{:?}を数値に変換できません", str),
+        Ok(x) => Ok(x),
+        Err(_) => Err(Error::format(format!("{:?}を数値に変換できません", str))),
+    }
+}
+
+#[inline]
+fn read_column<'a, I: Iterator<Item = &'a str>>(row: &mut I, name: &str) -> Result<&'a str> {
+    match row.next() {
+        Some(column) => Ok(column),
+        None => Err(Error::format(format!("{}が取得できません", name))),
     }
 }
 
+#[inline]
+fn parse_header(line: &str) -> Result<(u16, u16)> {
+    let mut row = line.split(' ');
+    let h = read_int(read_column(&mut row, "高さ")?)?;
+    let w = read_int(read_column(&mut row, "幅")?)?;
+    Ok((h, w))
+}
+
+#[inline]
+fn parse_row(line: &str) -> Result<(usize, usize, i16)> {
+    let mut row = line.split(' ');
+    let h = read_int(read_column(&mut row, "LeftID")?)?;
+    let w = read_int(read_column(&mut row, "RightID")?)?;
+    let c = read_int(read_column(&mut row, "コスト")?)?;
+    Ok((h, w, c))
+}
+
 pub struct Matrix {
     pub width: u16,
     pub height: u16,
@@ -17,34 +51,276 @@ pub struct Matrix {
 }
 
 impl Matrix {
+    /// 前の形態素の右文脈ID `src_id` と、次の形態素の左文脈ID `dst_id` の連接コスト
     #[inline]
     pub fn get(&self, src_id: u16, dst_id: u16) -> i16 {
-        self.cost[src_id as usize * self.height as usize + dst_id as usize]
-    }
-
-    pub fn load_from_file(file: &str) -> Matrix {
-        let file = File::open(file).expect("ファイルが開けません");
-        let file = BufReader::new(file);
-        let mut lines = file.lines().filter_map(Result::ok);
-        let (height, width) = {
-            let line: String = lines.next().expect("ヘッダ行がありません");
-            let mut row = line.split(" ");
-            let h = read_int(row.next().expect("No height"));
-            let w = read_int(row.next().expect("No width"));
-            (h, w)
+        debug_assert!(src_id < self.height && dst_id < self.width);
+        self.cost[src_id as usize * self.width as usize + dst_id as usize]
+    }
+
+    /// 単語の文脈IDがコスト行列の範囲に収まっているかを確かめる。
+    pub fn check_info(&self, info: &Info) -> Result<()> {
+        if info.left_id >= self.width {
+            let message = format!(
+                "LeftID {} がコスト行列の範囲外です (幅 {})",
+                info.left_id, self.width
+            );
+            return Err(Error::format(message));
+        }
+        if info.right_id >= self.height {
+            let message = format!(
+                "RightID {} がコスト行列の範囲外です (高さ {})",
+                info.right_id, self.height
+            );
+            return Err(Error::format(message));
+        }
+        Ok(())
+    }
+
+    /// 辞書の全単語の文脈IDがコスト行列の範囲に収まっているかを確かめる。
+    ///
+    /// 範囲外の文脈IDがあると `get` が別の値を読むため、辞書と行列を組み合わせる前に確かめる。
+    pub fn check_dict<K, D: PrefixMap<K, Info>>(&self, dic: &D) -> Result<()> {
+        let mut result = Ok(());
+        dic.each_entry(|_, infos| {
+            if result.is_ok() {
+                result = infos.iter().try_for_each(|info| self.check_info(info));
+            }
+        });
+        result
+    }
+
+    /// テキスト形式 (`matrix.def`) かバイナリ形式のファイルを読み込む。形式は先頭の識別子で判別する。
+    #[inline]
+    pub fn load_from_file(file: &str) -> Result<Matrix> {
+        Matrix::load_from_file_with_charset(file, Charset::Utf8)
+    }
+
+    /// テキスト形式の文字コードを指定して読み込む。
+    pub fn load_from_file_with_charset(file: &str, charset: Charset) -> Result<Matrix> {
+        let load = || {
+            let mut reader = BufReader::new(File::open(file)?);
+            if reader.fill_buf()?.starts_with(MAGIC) {
+                Matrix::from_binary_reader(reader)
+            } else {
+                Matrix::from_reader_with_charset(reader, charset)
+            }
         };
-        let mut cost = vec![0; width as usize * height as usize];
-        for line in lines {
-            let mut row = line.split(" ");
-            let h: usize = read_int(row.next().expect("No height"));
-            let w: usize = read_int(row.next().expect("No width"));
-            let c = read_int(row.next().expect("No cost"));
-            cost[h * height as usize + w] = c;
-        }
-        Matrix {
+        load().map_err(|e: Error| e.in_file(file))
+    }
+
+    /// テキスト形式 (`matrix.def`) を読み込む。
+    ///
+    /// 1 行目は `高さ 幅`、以降の行は `右文脈ID 左文脈ID コスト` とする。
+    /// 範囲外や重複した組み合わせ、コストのない組み合わせがあるとエラーになる。
+    #[inline]
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Matrix> {
+        Matrix::from_reader_with_charset(reader, Charset::Utf8)
+    }
+
+    /// 文字コードを指定してテキスト形式を読み込む。
+    pub fn from_reader_with_charset<R: BufRead>(mut reader: R, charset: Charset) -> Result<Matrix> {
+        let mut line_no = 0;
+        let mut next_line = || {
+            line_no += 1;
+            let mut line = String::new();
+            match charset.read_line(&mut reader, &mut line) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some((line_no, line))),
+                Err(e) => Err(e.at_line(line_no)),
+            }
+        };
+        let (height, width) = match next_line()? {
+            Some((_, line)) => parse_header(&line).map_err(|e| e.at_line(1))?,
+            None => return Err(Error::format("ヘッダ行がありません")),
+        };
+        if height == 0 || width == 0 {
+            return Err(Error::format("高さと幅は 1 以上にしてください").at_line(1));
+        }
+        let size = width as usize * height as usize;
+        let mut cost = vec![0; size];
+        // 読み込み済みの組み合わせ
+        let mut seen = vec![0u64; size.div_ceil(64)];
+        while let Some((line_no, line)) = next_line()? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (h, w, c) = parse_row(&line).map_err(|e| e.at_line(line_no))?;
+            if h >= height as usize || w >= width as usize {
+                let message = format!("{} {} は範囲外です (高さ {} 幅 {})", h, w, height, width);
+                return Err(Error::format(message).at_line(line_no));
+            }
+            let index = h * width as usize + w;
+            let (word, bit) = (index / 64, 1 << (index % 64));
+            if seen[word] & bit != 0 {
+                let message = format!("{} {} が重複しています", h, w);
+                return Err(Error::format(message).at_line(line_no));
+            }
+            seen[word] |= bit;
+            cost[index] = c;
+        }
+        let missing = (0..size).filter(|&ix| seen[ix / 64] & 1 << (ix % 64) == 0);
+        if let Some(first) = missing.clone().next() {
+            let (h, w) = (first / width as usize, first % width as usize);
+            let message = format!(
+                "{} {} のコストがありません (全 {} 件)",
+                h,
+                w,
+                missing.count()
+            );
+            return Err(Error::format(message));
+        }
+        Ok(Matrix {
             width,
             height,
             cost,
+        })
+    }
+
+    /// バイナリ形式で書き出す。
+    ///
+    /// 識別子、バージョン (u32)、高さ (u16)、幅 (u16) の後に、コストを i16 で並べる。
+    /// 数値はすべてリトルエンディアンで保存する。
+    pub fn save_binary<W: Write>(&self, mut out: W) -> Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        let bytes: Vec<u8> = self.cost.iter().flat_map(|c| c.to_le_bytes()).collect();
+        out.write_all(&bytes)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// バイナリ形式を読み込む。テキストの解析は行わず、コストをそのまま読み込む。
+    pub fn from_binary_reader<R: Read>(mut reader: R) -> Result<Matrix> {
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::format("ファイルが途中で終わっています"),
+            _ => Error::from(e),
+        };
+        let mut header = [0; 12];
+        reader.read_exact(&mut header).map_err(truncated)?;
+        if &header[..4] != MAGIC {
+            return Err(Error::format("コスト行列のバイナリ形式ではありません"));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != VERSION {
+            let message = format!("対応していないバージョンです ({})", version);
+            return Err(Error::format(message));
+        }
+        let height = u16::from_le_bytes([header[8], header[9]]);
+        let width = u16::from_le_bytes([header[10], header[11]]);
+
+        let mut bytes = vec![0; width as usize * height as usize * 2];
+        reader.read_exact(&mut bytes).map_err(truncated)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(Error::format("コスト行列の後に余分なデータがあります"));
         }
+        let cost = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Ok(Matrix {
+            width,
+            height,
+            cost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefix_map::PrefixMapInsert;
+
+    const MATRIX_DEF: &str = "2 3\n0 0 10\n0 1 0\n0 2 -5\n1 0 7\n1 1 300\n1 2 0\n";
+
+    fn line_of_error(text: &str) -> Option<usize> {
+        Matrix::from_reader(text.as_bytes()).err().unwrap().line()
+    }
+
+    #[test]
+    // "正方形でない行列でも正しい位置を参照する"
+    fn test_non_square() {
+        let matrix = Matrix::from_reader(MATRIX_DEF.as_bytes()).unwrap();
+        assert_eq!((matrix.height, matrix.width), (2, 3));
+        assert_eq!(matrix.get(0, 2), -5);
+        assert_eq!(matrix.get(1, 0), 7);
+        assert_eq!(matrix.get(1, 1), 300);
+    }
+
+    #[test]
+    // "辞書の文脈IDが範囲外ならエラーになる"
+    fn test_check_dict() {
+        let matrix = Matrix::from_reader(MATRIX_DEF.as_bytes()).unwrap();
+        let mut dic = crate::Trie::<u8, Info>::new();
+        dic.insert("a", Info::new(2, 1, 0));
+        assert!(matrix.check_dict(&dic).is_ok());
+        dic.insert("b", Info::new(3, 0, 0));
+        let err = matrix.check_dict(&dic).err().unwrap();
+        assert!(err.to_string().contains("LeftID 3"));
+    }
+
+    #[test]
+    // "テキスト形式の誤りは行番号付きのエラーになる"
+    fn test_text_error() {
+        assert_eq!(line_of_error("2 2\n0 0 1\n0 1 x\n"), Some(3));
+        // 範囲外
+        assert_eq!(line_of_error("2 3\n0 0 1\n2 0 1\n"), Some(3));
+        assert_eq!(line_of_error("2 3\n0 3 1\n"), Some(2));
+        // 重複
+        assert_eq!(line_of_error("1 2\n0 0 1\n\n0 1 1\n0 0 2\n"), Some(5));
+        // 不足
+        let err = Matrix::from_reader("2 2\n0 0 1\n1 1 1\n".as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), None);
+        assert_eq!(err.to_string(), "0 1 のコストがありません (全 2 件)");
+    }
+
+    #[test]
+    // "文字コードを指定して読み込み、変換できない行は行番号付きのエラーになる"
+    fn test_charset() {
+        let text = b"1 1\n0 0 5\n";
+        let matrix = Matrix::from_reader_with_charset(&text[..], Charset::ShiftJis).unwrap();
+        assert_eq!(matrix.get(0, 0), 5);
+        let text = b"1 1\n0 0 5 \xFF\n";
+        let err = Matrix::from_reader_with_charset(&text[..], Charset::EucJp)
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(2));
+    }
+
+    #[test]
+    // "文脈IDが範囲外の単語を検出する"
+    fn test_check_info() {
+        let matrix = Matrix::from_reader(MATRIX_DEF.as_bytes()).unwrap();
+        assert!(matrix.check_info(&Info::new(2, 1, 0)).is_ok());
+        assert!(matrix.check_info(&Info::new(3, 1, 0)).is_err());
+        assert!(matrix.check_info(&Info::new(2, 2, 0)).is_err());
+    }
+
+    #[test]
+    // "バイナリ形式で保存して読み込むと同じ行列になる"
+    fn test_binary() {
+        let matrix = Matrix::from_reader(MATRIX_DEF.as_bytes()).unwrap();
+        let mut bytes = vec![];
+        matrix.save_binary(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(bytes.len(), 12 + 2 * 6);
+
+        let loaded = Matrix::from_binary_reader(&bytes[..]).unwrap();
+        assert_eq!((loaded.height, loaded.width), (matrix.height, matrix.width));
+        assert_eq!(loaded.cost, matrix.cost);
+        assert_eq!(loaded.get(0, 2), -5);
+
+        assert!(Matrix::from_binary_reader(&bytes[..bytes.len() - 1]).is_err());
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(Matrix::from_binary_reader(&extra[..]).is_err());
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert!(Matrix::from_binary_reader(&version[..]).is_err());
+        assert!(Matrix::from_binary_reader(MATRIX_DEF.as_bytes()).is_err());
     }
 }
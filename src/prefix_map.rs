@@ -1,8 +1,317 @@
-use crate::as_chars::AsChars;
+use crate::as_chars::{AsChars, CodeUnit};
+use crate::fuzzy::Levenshtein;
+use std::mem;
 
 pub trait PrefixMap<K, V> {
     fn count(&self) -> usize;
     fn get<T: AsChars<K>>(&self, key: T) -> Option<&[V]>;
-    fn insert<T: AsChars<K>>(&mut self, key: T, value: V);
     fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, f: F);
+
+    /// `key` の先頭に一致する最も長いキーの長さと値を返す。
+    ///
+    /// 既定の実装は `each_prefix` の最後の結果を使う。
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[V])>
+    where
+        K: Copy,
+    {
+        let key: Vec<K> = key.as_chars().collect();
+        let mut longest = 0;
+        self.each_prefix(&key[..], |len, _| longest = len);
+        if longest == 0 {
+            return None;
+        }
+        self.get(&key[..longest]).map(|values| (longest, values))
+    }
+
+    /// 登録されているすべてのキーと値を `f` に渡す。
+    ///
+    /// キーは辞書の構造から復元し、同じバッファを使い回して渡す。
+    /// 辞書順に並べられるデータ構造では辞書順に渡す。
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, f: F)
+    where
+        V: 'a;
+
+    /// `prefix` で始まるキーとその値を `f` に渡す。`f` が `false` を返すと列挙を打ち切る。
+    ///
+    /// キーは `prefix` を含めて渡し、順序は `each_entry` と同じ。
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        prefix: T,
+        f: F,
+    ) where
+        V: 'a;
+
+    /// `prefix` で始まるキーとその値を最大 `limit` 件集める。
+    fn predict<T: AsChars<K>>(&self, prefix: T, limit: usize) -> Vec<(Vec<K>, &[V])>
+    where
+        K: Clone,
+    {
+        let mut entries = vec![];
+        if limit > 0 {
+            self.each_predictive(prefix, |key, values| {
+                entries.push((key.to_vec(), values));
+                entries.len() < limit
+            });
+        }
+        entries
+    }
+
+    /// `key` との編集距離 (レーベンシュタイン距離) が `max_distance` 以下のキーを、
+    /// 距離と値とともに `f` に渡す。`f` が `false` を返すと列挙を打ち切る。
+    ///
+    /// 距離はキーの単位 (バイトや UTF-16 の符号単位) ではなく文字で数える。
+    /// 順序は `each_entry` と同じ。距離の上限を超えた枝はそれ以上辿らない。
+    ///
+    /// 既定の実装は `each_entry` で渡されるすべてのキーについて距離を求める。
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+        V: 'a,
+    {
+        let mut lev = Levenshtein::new(key.as_chars().collect(), max_distance);
+        let mut done = false;
+        self.each_entry(|key, values| {
+            if done {
+                return;
+            }
+            for &unit in key {
+                lev.push(unit);
+            }
+            if let Some(distance) = lev.distance() {
+                done = !f(key, distance, values);
+            }
+            for _ in key {
+                lev.pop();
+            }
+        });
+    }
+
+    /// `key` との編集距離が `max_distance` 以下のキーを、距離と値とともに最大 `limit` 件集める。
+    fn fuzzy<T: AsChars<K>>(
+        &self,
+        key: T,
+        max_distance: usize,
+        limit: usize,
+    ) -> Vec<(Vec<K>, usize, &[V])>
+    where
+        K: CodeUnit,
+    {
+        let mut entries = vec![];
+        if limit > 0 {
+            self.each_fuzzy(key, max_distance, |key, distance, values| {
+                entries.push((key.to_vec(), distance, values));
+                entries.len() < limit
+            });
+        }
+        entries
+    }
+
+    /// 登録されているすべてのキーと値を `each_entry` と同じ順に集める。
+    fn entries(&self) -> Vec<(Vec<K>, &[V])>
+    where
+        K: Clone,
+    {
+        let mut entries = vec![];
+        self.each_entry(|key, values| entries.push((key.to_vec(), values)));
+        entries
+    }
+}
+
+/// 値をすべて取り出す。値がなければ `None` を返す。
+pub(crate) fn take_values<V>(data: &mut Vec<V>) -> Option<Vec<V>> {
+    if data.is_empty() {
+        None
+    } else {
+        Some(mem::take(data))
+    }
+}
+
+/// キーと値を登録できる辞書。読み込み専用の辞書は実装しない。
+pub trait PrefixMapInsert<K, V>: PrefixMap<K, V> {
+    fn insert<T: AsChars<K>>(&mut self, key: T, value: V);
+}
+
+/// キーや値を取り除ける辞書。読み込み専用の辞書は実装しない。
+pub trait PrefixMapMut<K, V>: PrefixMap<K, V> {
+    /// キーとその値をすべて取り除き、取り除いた値を返す。キーがなければ `None` を返す。
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<V>>;
+
+    /// キーの値のうち `value` と等しいものを一つ取り除く。取り除いた場合は `true` を返す。
+    ///
+    /// 値がなくなったキーは `remove` と同じく取り除く。
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &V) -> bool
+    where
+        V: PartialEq;
+}
+
+/// テストで期待する結果のキーを `String` にする。
+#[cfg(test)]
+pub(crate) fn owned_keys<T>(entries: Vec<(&str, T)>) -> Vec<(String, T)> {
+    entries
+        .into_iter()
+        .map(|(key, rest)| (key.to_owned(), rest))
+        .collect()
+}
+
+/// `value` と等しい値を一つ取り除く。
+pub(crate) fn remove_one<V: PartialEq>(data: &mut Vec<V>, value: &V) -> Option<()> {
+    let ix = data.iter().position(|v| v == value)?;
+    data.remove(ix);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::double_array::DoubleArray;
+    use crate::recursive_hash_map::RecursiveHashMap;
+    use crate::search_cache::NoCache;
+    use crate::trie::Trie;
+
+    fn build<M: PrefixMapInsert<u8, i32> + Default>(entries: &[(&str, i32)]) -> M {
+        let mut map = M::default();
+        for &(key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// 同じキーを登録した各実装で `$check` を呼ぶ。`Plain` では既定の実装を確かめる。
+    macro_rules! check_all {
+        ($entries:expr, $check:ident) => {
+            $check(&build::<Trie<u8, i32>>($entries));
+            $check(&build::<DoubleArray<u8, i32, NoCache>>($entries));
+            $check(&build::<RecursiveHashMap<u8, i32>>($entries));
+            $check(&Plain(build($entries)));
+        };
+    }
+
+    /// 結果のキーを `String` にする。
+    fn utf8_keys<T>(entries: Vec<(Vec<u8>, T)>) -> Vec<(String, T)> {
+        entries
+            .into_iter()
+            .map(|(key, rest)| (String::from_utf8(key).unwrap(), rest))
+            .collect()
+    }
+
+    /// 既定の実装を確かめるため、必須のメソッドだけを実装した辞書
+    struct Plain(Trie<u8, i32>);
+
+    impl PrefixMap<u8, i32> for Plain {
+        fn count(&self) -> usize {
+            self.0.count()
+        }
+
+        fn get<T: AsChars<u8>>(&self, key: T) -> Option<&[i32]> {
+            self.0.get(key)
+        }
+
+        fn each_prefix<T: AsChars<u8>, F: FnMut(usize, &[i32])>(&self, key: T, f: F) {
+            self.0.each_prefix(key, f)
+        }
+
+        fn each_entry<'a, F: FnMut(&[u8], &'a [i32])>(&'a self, f: F) {
+            self.0.each_entry(f)
+        }
+
+        fn each_predictive<'a, T: AsChars<u8>, F: FnMut(&[u8], &'a [i32]) -> bool>(
+            &'a self,
+            prefix: T,
+            f: F,
+        ) {
+            self.0.each_predictive(prefix, f)
+        }
+    }
+
+    #[test]
+    // "前方一致するキーを辞書順に列挙する"
+    fn test_predictive() {
+        fn check<M: PrefixMap<u8, i32>>(pt: &M) {
+            let keys = |prefix: &str, limit| -> Vec<(String, Vec<i32>)> {
+                let found = pt.predict(prefix, limit);
+                utf8_keys(found.into_iter().map(|(k, v)| (k, v.to_vec())).collect())
+            };
+            let expected = owned_keys(vec![("ab", vec![2]), ("abc", vec![1]), ("abd", vec![4])]);
+            assert_eq!(keys("ab", 10), expected);
+            assert_eq!(keys("a", 2), expected[..2].to_vec());
+            assert_eq!(keys("x", 10), vec![]);
+            assert_eq!(keys("", 10).len(), 5);
+            assert!(keys("", 0).is_empty());
+
+            // f が false を返すと打ち切る
+            let mut count = 0;
+            pt.each_predictive("", |_, _| {
+                count += 1;
+                count < 3
+            });
+            assert_eq!(count, 3);
+        }
+        let entries = [("abc", 1), ("ab", 2), ("b", 3), ("abd", 4), ("ac", 5)];
+        check_all!(&entries, check);
+    }
+
+    #[test]
+    // "最長一致検索"
+    fn test_longest_match() {
+        fn check<M: PrefixMap<u8, i32>>(pt: &M) {
+            assert_eq!(pt.longest_match("abcd"), Some((3, &[1][..])));
+            assert_eq!(pt.longest_match("abx"), Some((1, &[2, 3][..])));
+            assert_eq!(pt.longest_match("abcdef"), Some((5, &[4][..])));
+            assert_eq!(pt.longest_match("b"), None);
+            assert_eq!(pt.longest_match(""), None);
+        }
+        let entries = [("abc", 1), ("a", 2), ("a", 3), ("abcde", 4)];
+        check_all!(&entries, check);
+    }
+
+    #[test]
+    // "編集距離が上限以内のキーを列挙する"
+    fn test_fuzzy() {
+        fn check<M: PrefixMap<u8, i32>>(pt: &M) {
+            let found = |key: &str, max_distance, limit| -> Vec<(String, (usize, Vec<i32>))> {
+                let found = pt.fuzzy(key, max_distance, limit);
+                let found = found.into_iter().map(|(k, d, v)| (k, (d, v.to_vec())));
+                utf8_keys(found.collect())
+            };
+            let expected = owned_keys(vec![
+                ("abc", (0, vec![1])),
+                ("abd", (1, vec![2])),
+                ("ac", (1, vec![3])),
+                ("bbc", (1, vec![4])),
+            ]);
+            assert_eq!(found("abc", 1, 10), expected);
+            assert_eq!(found("abc", 1, 2), expected[..2].to_vec());
+            assert_eq!(found("abc", 0, 10), expected[..1].to_vec());
+            assert_eq!(found("", 2, 10), owned_keys(vec![("ac", (2, vec![3]))]));
+            assert_eq!(found("abc", 3, 10).len(), 5);
+
+            // f が false を返すと打ち切る
+            let mut count = 0;
+            pt.each_fuzzy("abc", 3, |_, _, _| {
+                count += 1;
+                false
+            });
+            assert_eq!(count, 1);
+        }
+        let entries = [("abc", 1), ("abd", 2), ("ac", 3), ("bbc", 4), ("xyz", 5)];
+        check_all!(&entries, check);
+    }
+
+    #[test]
+    // "多バイト文字もバイトではなく一文字として数える"
+    fn test_fuzzy_multibyte() {
+        fn check<M: PrefixMap<u8, i32>>(pt: &M) {
+            let found = pt.fuzzy("すもも", 1, 10);
+            let found = found.into_iter().map(|(k, d, _)| (k, d)).collect();
+            let expected = owned_keys(vec![("すもう", 1), ("すもも", 0), ("もも", 1)]);
+            assert_eq!(utf8_keys(found), expected);
+            assert_eq!(pt.fuzzy("すもも", 2, 10).len(), 4);
+        }
+        let entries = [("すもも", 1), ("すもう", 2), ("もも", 3), ("すし", 4)];
+        check_all!(&entries, check);
+    }
 }
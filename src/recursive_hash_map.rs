@@ -1,14 +1,51 @@
 use fnv::FnvHashMap as HashMap;
 // use std::collections::HashMap;
-use crate::as_chars::AsChars;
-use crate::prefix_map::PrefixMap;
+use crate::as_chars::{AsChars, CodeUnit};
+use crate::fuzzy::Levenshtein;
+use crate::prefix_map::{remove_one, take_values, PrefixMap, PrefixMapInsert, PrefixMapMut};
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
 use std::hash::Hash;
 
 #[derive(Serialize, Deserialize)]
+#[serde(
+    from = "Links<K, V>",
+    bound(deserialize = "K: Copy + Ord + Hash + Deserialize<'de>, V: Deserialize<'de>")
+)]
 pub struct RecursiveHashMap<K: Eq + Hash, V> {
     id: u32,
     link: HashMap<(u32, K), u32>,
     data: HashMap<u32, Vec<V>>,
+    /// ノードごとの子の文字。文字の順に並べ、遷移と一緒に更新する。
+    /// ファイルには書き出さず、読み込むときに遷移から作り直す。
+    #[serde(skip_serializing)]
+    children: HashMap<u32, Vec<K>>,
+}
+
+/// ファイルに書き出した `RecursiveHashMap` の内容。
+#[derive(Deserialize)]
+struct Links<K: Eq + Hash, V> {
+    id: u32,
+    link: HashMap<(u32, K), u32>,
+    data: HashMap<u32, Vec<V>>,
+}
+
+impl<K: Copy + Ord + Hash, V> From<Links<K, V>> for RecursiveHashMap<K, V> {
+    fn from(links: Links<K, V>) -> Self {
+        let mut children = HashMap::<u32, Vec<K>>::default();
+        for &(parent, ch) in links.link.keys() {
+            children.entry(parent).or_default().push(ch);
+        }
+        for chars in children.values_mut() {
+            chars.sort_unstable();
+        }
+        RecursiveHashMap {
+            id: links.id,
+            link: links.link,
+            data: links.data,
+            children,
+        }
+    }
 }
 
 impl<K: Eq + Hash, V> RecursiveHashMap<K, V> {
@@ -18,6 +55,7 @@ impl<K: Eq + Hash, V> RecursiveHashMap<K, V> {
             id: 0,
             link: Default::default(),
             data: Default::default(),
+            children: Default::default(),
         }
     }
 }
@@ -28,7 +66,7 @@ impl<K: Eq + Hash, V> Default for RecursiveHashMap<K, V> {
     }
 }
 
-impl<K: Eq + Hash, V> PrefixMap<K, V> for RecursiveHashMap<K, V> {
+impl<K: Copy + Ord + Hash, V> PrefixMap<K, V> for RecursiveHashMap<K, V> {
     #[inline]
     fn count(&self) -> usize {
         self.data.values().map(|v| v.len()).sum()
@@ -49,25 +87,6 @@ impl<K: Eq + Hash, V> PrefixMap<K, V> for RecursiveHashMap<K, V> {
         }
     }
 
-    #[inline]
-    fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
-        let id = &mut self.id;
-        let link = &mut self.link;
-        let data = &mut self.data;
-
-        let mut current_id = 0;
-        for ch in key.as_chars() {
-            let entry = link.entry((current_id, ch));
-            let next_id = entry.or_insert_with(|| {
-                *id += 1;
-                *id
-            });
-            current_id = *next_id;
-        }
-        let vec = data.entry(current_id).or_insert_with(Default::default);
-        vec.push(value);
-    }
-
     #[inline]
     fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, mut f: F) {
         let mut current_id = 0;
@@ -83,11 +102,205 @@ impl<K: Eq + Hash, V> PrefixMap<K, V> for RecursiveHashMap<K, V> {
             }
         }
     }
+
+    #[inline]
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[V])> {
+        let mut current_id = 0;
+        let mut longest = None;
+        for (ix, ch) in key.as_chars().enumerate() {
+            match self.link.get(&(current_id, ch)) {
+                Some(next_id) => current_id = *next_id,
+                None => break,
+            }
+            if let Some(vec) = self.data.get(&current_id) {
+                longest = Some((ix + 1, &vec[..]));
+            }
+        }
+        longest
+    }
+
+    #[inline]
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, mut f: F)
+    where
+        V: 'a,
+    {
+        self.visit_from(0, &mut vec![], &mut |key, values| {
+            f(key, values);
+            true
+        });
+    }
+
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+        V: 'a,
+    {
+        fn visit<'a, K: CodeUnit + Ord + Hash, V, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+            id: u32,
+            key: &mut Vec<K>,
+            lev: &mut Levenshtein<K>,
+            map: &'a RecursiveHashMap<K, V>,
+            f: &mut F,
+        ) -> bool {
+            if let (Some(values), Some(distance)) = (map.data.get(&id), lev.distance()) {
+                if !values.is_empty() && !f(key, distance, &values[..]) {
+                    return false;
+                }
+            }
+            for (ch, child) in map.children_of(id) {
+                let reachable = lev.push(ch);
+                key.push(ch);
+                let cont = !reachable || visit(child, key, lev, map, f);
+                key.pop();
+                lev.pop();
+                if !cont {
+                    return false;
+                }
+            }
+            true
+        }
+        let mut lev = Levenshtein::new(key.as_chars().collect(), max_distance);
+        visit(0, &mut vec![], &mut lev, self, &mut f);
+    }
+
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        prefix: T,
+        mut f: F,
+    ) where
+        V: 'a,
+    {
+        let mut key = vec![];
+        let mut current_id = 0;
+        for ch in prefix.as_chars() {
+            match self.link.get(&(current_id, ch)) {
+                Some(next_id) => current_id = *next_id,
+                None => return,
+            }
+            key.push(ch);
+        }
+        self.visit_from(current_id, &mut key, &mut f);
+    }
+}
+
+impl<K: Copy + Ord + Hash, V> PrefixMapInsert<K, V> for RecursiveHashMap<K, V> {
+    #[inline]
+    fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
+        let mut current_id = 0;
+        for ch in key.as_chars() {
+            current_id = match self.link.entry((current_id, ch)) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    self.id += 1;
+                    entry.insert(self.id);
+                    let chars = self.children.entry(current_id).or_default();
+                    let ix = chars.binary_search(&ch).unwrap_err();
+                    chars.insert(ix, ch);
+                    self.id
+                }
+            };
+        }
+        let vec = self.data.entry(current_id).or_insert_with(Default::default);
+        vec.push(value);
+    }
+}
+
+impl<K: Copy + Ord + Hash, V> PrefixMapMut<K, V> for RecursiveHashMap<K, V> {
+    #[inline]
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<V>> {
+        self.remove_with(key, take_values)
+    }
+
+    #[inline]
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let remove = |data: &mut Vec<V>| remove_one(data, value);
+        self.remove_with(key, remove).is_some()
+    }
+}
+
+impl<K: Copy + Ord + Hash, V> RecursiveHashMap<K, V> {
+    /// キーの値を `f` で取り除き、値も子もなくなったノードを遷移とともに削除する。
+    fn remove_with<T: AsChars<K>, R, F: FnOnce(&mut Vec<V>) -> Option<R>>(
+        &mut self,
+        key: T,
+        f: F,
+    ) -> Option<R> {
+        let mut path = vec![];
+        let mut current_id = 0;
+        for ch in key.as_chars() {
+            path.push((current_id, ch));
+            current_id = *self.link.get(&(current_id, ch))?;
+        }
+        let data = self.data.get_mut(&current_id)?;
+        let result = f(data)?;
+        if !data.is_empty() {
+            return Some(result);
+        }
+        self.data.remove(&current_id);
+        for (parent, ch) in path.into_iter().rev() {
+            if self.data.contains_key(&current_id) || self.children.contains_key(&current_id) {
+                break;
+            }
+            self.link.remove(&(parent, ch));
+            if let Some(chars) = self.children.get_mut(&parent) {
+                chars.retain(|&c| c != ch);
+                if chars.is_empty() {
+                    self.children.remove(&parent);
+                }
+            }
+            current_id = parent;
+        }
+        Some(result)
+    }
+
+    /// `id` 以下のキーと値を辞書順に渡す。`f` が `false` を返すと打ち切る。
+    fn visit_from<'a, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        id: u32,
+        key: &mut Vec<K>,
+        f: &mut F,
+    ) {
+        fn visit<'a, K: Copy + Ord + Hash, V, F: FnMut(&[K], &'a [V]) -> bool>(
+            id: u32,
+            key: &mut Vec<K>,
+            map: &'a RecursiveHashMap<K, V>,
+            f: &mut F,
+        ) -> bool {
+            if let Some(values) = map.data.get(&id) {
+                if !values.is_empty() && !f(key, &values[..]) {
+                    return false;
+                }
+            }
+            for (ch, child) in map.children_of(id) {
+                key.push(ch);
+                if !visit(child, key, map, f) {
+                    return false;
+                }
+                key.pop();
+            }
+            true
+        }
+        visit(id, key, self, f);
+    }
+
+    /// `id` の子の文字と ID を文字の順に返す。
+    fn children_of(&self, id: u32) -> impl Iterator<Item = (K, u32)> + '_ {
+        let chars = self.children.get(&id).map_or(&[][..], |chars| &chars[..]);
+        chars.iter().map(move |&ch| (ch, self.link[&(id, ch)]))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prefix_map::owned_keys;
     type RecursiveHashMap<T> = super::RecursiveHashMap<u8, T>;
 
     #[test]
@@ -190,4 +403,77 @@ mod tests {
         });
         assert_eq!(vec, vec![(1, vec![4, 5]), (3, vec![1])]);
     }
+
+    #[test]
+    // "登録したキーと値を辞書順に列挙する"
+    fn test_each_entry() {
+        let mut pt = RecursiveHashMap::new();
+        pt.insert("ba", 1);
+        pt.insert("abc", 2);
+        pt.insert("a", 3);
+        pt.insert("ba", 4);
+
+        let mut vec = vec![];
+        pt.each_entry(|key, data| {
+            vec.push((String::from_utf8(key.to_owned()).unwrap(), data.to_owned()));
+        });
+        let expected = owned_keys(vec![("a", vec![3]), ("abc", vec![2]), ("ba", vec![1, 4])]);
+        assert_eq!(vec, expected);
+    }
+
+    #[test]
+    // "辿ったあとに加えたキーも辿れる"
+    fn test_insert_after_walk() {
+        let mut pt = RecursiveHashMap::new();
+        pt.insert("ab", 1);
+        assert_eq!(pt.predict("a", 10).len(), 1);
+        pt.insert("ac", 2);
+        pt.insert("ab", 3);
+        let keys: Vec<_> = pt
+            .predict("a", 10)
+            .into_iter()
+            .map(|(key, data)| (String::from_utf8(key).unwrap(), data.to_owned()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![("ab".to_owned(), vec![1, 3]), ("ac".to_owned(), vec![2])]
+        );
+    }
+
+    #[test]
+    // "読み込んだあとも子の一覧から辞書順に辿れる"
+    fn test_deserialize() {
+        let mut pt = RecursiveHashMap::new();
+        for (key, value) in [("ba", 1), ("abc", 2), ("a", 3), ("ab", 4)] {
+            pt.insert(key, value);
+        }
+        let bytes = bincode::serialize(&pt).unwrap();
+        let mut loaded: RecursiveHashMap<i32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.entries(), pt.entries());
+        loaded.insert("aa", 5);
+        assert_eq!(
+            loaded.predict("a", 2),
+            vec![(b"a".to_vec(), &[3][..]), (b"aa".to_vec(), &[5][..])]
+        );
+    }
+
+    #[test]
+    // "キーと値を取り除く"
+    fn test_remove() {
+        let mut pt = RecursiveHashMap::new();
+        pt.insert("abc", 1);
+        pt.insert("ab", 2);
+        pt.insert("ab", 5);
+
+        assert_eq!(pt.remove("ab"), Some(vec![2, 5]));
+        assert_eq!(pt.get("ab"), None);
+        assert_eq!(pt.remove("ab"), None);
+        assert_eq!(pt.remove("a"), None);
+        assert_eq!(pt.link.len(), 3);
+        assert!(pt.remove_value("abc", &1));
+        assert!(!pt.remove_value("abc", &1));
+        assert_eq!(pt.count(), 0);
+        assert!(pt.entries().is_empty());
+        assert!(pt.link.is_empty() && pt.children.is_empty());
+    }
 }
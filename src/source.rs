@@ -0,0 +1,632 @@
+use crate::as_chars::AsChars;
+use crate::charset::Charset;
+use crate::error::{Error, Result};
+use crate::feature::Features;
+use crate::info::Info;
+use crate::matrix::Matrix;
+use crate::prefix_map::PrefixMapInsert;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+// MeCab 形式の辞書ソース (CSV) を読み込む。
+// フィールドは RFC 4180 に従い、`"` で囲むとカンマや改行を含められる。`"` 自体は `""` と書く。
+
+/// CSV の一つのフィールド
+struct Field<'a> {
+    /// 行頭からのバイト位置
+    start: usize,
+    /// 引用符を除いた中身。引用符で囲まれていた場合、`""` はそのまま残る
+    value: &'a str,
+    quoted: bool,
+}
+
+impl<'a> Field<'a> {
+    #[inline]
+    fn plain(start: usize, value: &'a str) -> Self {
+        Field {
+            start,
+            value,
+            quoted: false,
+        }
+    }
+
+    /// `""` を `"` に戻した中身。戻す必要がなければ借用したまま返す。
+    #[inline]
+    fn unescape(&self) -> Cow<'a, str> {
+        if self.quoted && self.value.contains("\"\"") {
+            Cow::Owned(self.value.replace("\"\"", "\""))
+        } else {
+            Cow::Borrowed(self.value)
+        }
+    }
+}
+
+enum FieldError {
+    /// 引用符が閉じられていない
+    Unterminated,
+    /// 閉じ引用符の後にカンマ以外の文字がある
+    AfterQuote,
+}
+
+impl From<FieldError> for Error {
+    fn from(e: FieldError) -> Self {
+        match e {
+            FieldError::Unterminated => Error::format("引用符が閉じられていません"),
+            FieldError::AfterQuote => Error::format("閉じ引用符の後にカンマ以外の文字があります"),
+        }
+    }
+}
+
+/// CSV のフィールドを順に取り出す。
+struct Fields<'a> {
+    text: &'a str,
+    next: Option<usize>,
+}
+
+impl<'a> Fields<'a> {
+    #[inline]
+    fn new(text: &'a str) -> Self {
+        Fields {
+            text,
+            next: Some(0),
+        }
+    }
+
+    fn field(&self, start: usize) -> std::result::Result<(Field<'a>, Option<usize>), FieldError> {
+        let rest = &self.text[start..];
+        if !rest.starts_with('"') {
+            return Ok(match rest.find(',') {
+                Some(pos) => (Field::plain(start, &rest[..pos]), Some(start + pos + 1)),
+                None => (Field::plain(start, rest), None),
+            });
+        }
+        let bytes = rest.as_bytes();
+        let mut pos = 1;
+        loop {
+            let quote = match rest[pos..].find('"') {
+                Some(quote) => pos + quote,
+                None => return Err(FieldError::Unterminated),
+            };
+            let field = Field {
+                start,
+                value: &rest[1..quote],
+                quoted: true,
+            };
+            match bytes.get(quote + 1) {
+                Some(b'"') => pos = quote + 2,
+                Some(b',') => return Ok((field, Some(start + quote + 2))),
+                Some(_) => return Err(FieldError::AfterQuote),
+                None => return Ok((field, None)),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = std::result::Result<Field<'a>, FieldError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next?;
+        let result = self.field(start);
+        self.next = match &result {
+            Ok((_, next)) => *next,
+            Err(_) => None,
+        };
+        Some(result.map(|(field, _)| field))
+    }
+}
+
+/// 素性文字列を列ごとに分ける。引用符で囲まれた列は引用符を除き、`""` を `"` に戻す。
+/// 引用符が正しくない列があればエラーを返し、それ以降の列は返さない。
+pub(crate) fn split_feature(raw: &str) -> impl Iterator<Item = Result<Cow<'_, str>>> {
+    Fields::new(raw).map(|field| Ok(field?.unescape()))
+}
+
+/// 辞書ソースの一つの単語
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// 表層形
+    pub surface: String,
+    pub left_id: u16,
+    pub right_id: u16,
+    pub cost: i16,
+    /// コスト以降の列。辞書に書かれていたままの文字列で、ない場合は `None`
+    pub feature: Option<String>,
+}
+
+impl Entry {
+    /// CSV の一行 (引用符の中の改行を含む) を読み込む。
+    pub fn parse(text: &str) -> Result<Entry> {
+        let mut fields = Fields::new(text);
+        let mut next = |name: &str| match fields.next() {
+            Some(field) => Ok(field?),
+            None => Err(Error::format(format!("{}が取得できません", name))),
+        };
+        let surface = next("表層形")?.unescape().into_owned();
+        if surface.is_empty() {
+            return Err(Error::format("表層形が空です"));
+        }
+        let left_id = read_int(&next("LeftID")?)?;
+        let right_id = read_int(&next("RightID")?)?;
+        let cost = read_int(&next("コスト")?)?;
+        let feature = match fields.next() {
+            Some(Ok(field)) => Some(text[field.start..].to_owned()),
+            Some(Err(_)) => return Err(Error::format("素性の引用符が正しくありません")),
+            None => None,
+        };
+        // 残りの素性の列の引用符も確かめておく
+        if fields.any(|field| field.is_err()) {
+            return Err(Error::format("素性の引用符が正しくありません"));
+        }
+        Ok(Entry {
+            surface,
+            left_id,
+            right_id,
+            cost,
+            feature,
+        })
+    }
+
+    /// 素性を `features` に登録して `Info` を作る。`features` がない場合は素性を持たない。
+    pub fn to_info(&self, features: Option<&mut Features>) -> Result<Info> {
+        let feature = match (features, &self.feature) {
+            (Some(features), Some(feature)) => features.insert(feature)?,
+            _ => Info::NO_FEATURE,
+        };
+        Ok(Info::with_feature(
+            self.left_id,
+            self.right_id,
+            self.cost,
+            feature,
+        ))
+    }
+}
+
+/// CSV のフィールドとして書き出せるよう、必要な場合は引用符で囲む。
+fn quote_field(value: &str) -> Cow<'_, str> {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// `Entry::parse` で読み込める CSV の一行として書き出す。素性は元の文字列のまま続ける。
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&quote_field(&self.surface))?;
+        write!(f, ",{},{},{}", self.left_id, self.right_id, self.cost)?;
+        if let Some(feature) = &self.feature {
+            write!(f, ",{}", feature)?;
+        }
+        Ok(())
+    }
+}
+
+/// 辞書ソースの列の並び
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SourceFormat {
+    /// IPADIC。コスト以降の列をそのまま素性にする。
+    Ipadic,
+    /// UniDic。品詞、活用型、活用形、書字形基本形、仮名形出現形、発音形出現形を素性にする。
+    Unidic,
+    /// Sudachi。品詞、活用型、活用形、正規化表記、読みを素性にする。
+    /// 文脈IDが -1 の単語は解析に使わない単語なので読み飛ばす。
+    Sudachi,
+}
+
+/// 素性の各列 (品詞、品詞細分類1〜3、活用型、活用形、原形、読み、発音) が、
+/// 辞書ソースの何列目 (0 始まり) にあるか
+struct Layout {
+    features: [usize; 9],
+    /// 文脈IDが -1 の単語を読み飛ばす
+    skip_unused: bool,
+}
+
+const UNIDIC: Layout = Layout {
+    features: [4, 5, 6, 7, 8, 9, 14, 21, 13],
+    skip_unused: false,
+};
+
+const SUDACHI: Layout = Layout {
+    features: [5, 6, 7, 8, 9, 10, 12, 11, 11],
+    skip_unused: true,
+};
+
+impl Default for SourceFormat {
+    #[inline]
+    fn default() -> Self {
+        SourceFormat::Ipadic
+    }
+}
+
+impl FromStr for SourceFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ipadic" => Ok(SourceFormat::Ipadic),
+            "unidic" => Ok(SourceFormat::Unidic),
+            "sudachi" => Ok(SourceFormat::Sudachi),
+            _ => Err(Error::format(format!(
+                "{} は対応していない辞書の形式です",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SourceFormat::Ipadic => "IPADIC",
+            SourceFormat::Unidic => "UniDic",
+            SourceFormat::Sudachi => "Sudachi",
+        };
+        f.write_str(name)
+    }
+}
+
+impl SourceFormat {
+    /// CSV の一行 (引用符の中の改行を含む) を読み込む。
+    /// 素性は IPADIC の列順に並べ替え、`Feature::parse` で読める形にする。
+    /// 解析に使わない単語の場合は `None` を返す。
+    pub fn parse(self, text: &str) -> Result<Option<Entry>> {
+        let layout = match self {
+            SourceFormat::Ipadic => return Entry::parse(text).map(Some),
+            SourceFormat::Unidic => &UNIDIC,
+            SourceFormat::Sudachi => &SUDACHI,
+        };
+        let fields = Fields::new(text)
+            .map(|field| Ok(field?.unescape()))
+            .collect::<Result<Vec<_>>>()?;
+        if layout.skip_unused && fields.iter().skip(1).take(2).any(|id| id.trim() == "-1") {
+            return Ok(None);
+        }
+        let mut entry = Entry::parse(text)?;
+        let column = |ix: usize| match fields.get(ix) {
+            Some(value) if !value.is_empty() => value.as_ref(),
+            _ => "*",
+        };
+        let feature: Vec<_> = layout
+            .features
+            .iter()
+            .map(|&ix| quote_field(column(ix)))
+            .collect();
+        entry.feature = Some(feature.join(","));
+        Ok(Some(entry))
+    }
+}
+
+#[inline]
+fn read_int<T: FromStr>(field: &Field<'_>) -> Result<T> {
+    let value = field.value;
+    match value.trim().parse() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(Error::format(format!("{:?}を数値に変換できません", value))),
+    }
+}
+
+/// 辞書ソースを一単語ずつ読み込む。空行は読み飛ばす。
+/// エラーには読み込んでいた単語の開始行の番号が付く。
+pub struct SourceReader<R> {
+    reader: R,
+    charset: Charset,
+    format: SourceFormat,
+    // 最後に読み込んだ単語の開始行
+    line: usize,
+    // 読み込んだ行数
+    read: usize,
+}
+
+impl<R: BufRead> SourceReader<R> {
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        SourceReader::with_charset(reader, Charset::Utf8)
+    }
+
+    /// 文字コードを指定して読み込む。
+    #[inline]
+    pub fn with_charset(reader: R, charset: Charset) -> Self {
+        SourceReader {
+            reader,
+            charset,
+            format: SourceFormat::Ipadic,
+            line: 0,
+            read: 0,
+        }
+    }
+
+    /// 辞書ソースの列の並びを指定する。
+    #[inline]
+    pub fn with_format(mut self, format: SourceFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// 最後に読み込んだ単語の開始行の番号 (1 始まり)
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// すべての単語を `dic` に登録し、登録した件数を返す。
+    /// `features` を指定すると素性も登録し、`matrix` を指定すると文脈IDが範囲内かを確かめる。
+    pub fn insert_into<K, D>(
+        mut self,
+        dic: &mut D,
+        mut features: Option<&mut Features>,
+        matrix: Option<&Matrix>,
+    ) -> Result<usize>
+    where
+        for<'a> &'a str: AsChars<K>,
+        D: PrefixMapInsert<K, Info>,
+    {
+        let mut count = 0;
+        while let Some(entry) = self.next() {
+            let entry = entry?;
+            let info = entry
+                .to_info(features.as_deref_mut())
+                .map_err(|e| e.at_line(self.line))?;
+            if let Some(matrix) = matrix {
+                matrix.check_info(&info).map_err(|e| e.at_line(self.line))?;
+            }
+            dic.insert(entry.surface.as_str(), info);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// 空行を読み飛ばして一単語分の行を読み込む。引用符の中で改行されている場合は次の行とつなげる。
+    fn read_record(&mut self) -> Option<Result<String>> {
+        let mut text = String::new();
+        loop {
+            text.clear();
+            match self.read_line(&mut text) {
+                Ok(0) => return None,
+                Ok(_) if text.trim().is_empty() => continue,
+                Ok(_) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.line = self.read;
+        while Fields::new(&text).any(|f| matches!(f, Err(FieldError::Unterminated))) {
+            text.push('\n');
+            match self.read_line(&mut text) {
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(text))
+    }
+
+    /// 一行を `buf` の後ろに読み込む。末尾の改行は除く。
+    fn read_line(&mut self, buf: &mut String) -> Result<usize> {
+        self.read += 1;
+        let line = self.read;
+        self.charset
+            .read_line(&mut self.reader, buf)
+            .map_err(|e| e.at_line(line))
+    }
+}
+
+impl<R: BufRead> Iterator for SourceReader<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.read_record()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.format.parse(&text) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e.at_line(self.line))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Feature, PrefixMap, Trie};
+
+    fn entries(text: &str) -> Vec<Result<Entry>> {
+        SourceReader::new(text.as_bytes()).collect()
+    }
+
+    #[test]
+    // "引用符のない行"
+    fn test_plain() {
+        let entry =
+            Entry::parse("すもも,1285,1285,7546,名詞,一般,*,*,*,*,すもも,スモモ,スモモ").unwrap();
+        assert_eq!(entry.surface, "すもも");
+        assert_eq!(
+            (entry.left_id, entry.right_id, entry.cost),
+            (1285, 1285, 7546)
+        );
+        assert_eq!(
+            entry.feature.as_deref(),
+            Some("名詞,一般,*,*,*,*,すもも,スモモ,スモモ")
+        );
+        let entry = Entry::parse("a,1,2,-3").unwrap();
+        assert_eq!(entry.cost, -3);
+        assert_eq!(entry.feature, None);
+    }
+
+    #[test]
+    // "引用符で囲まれた列にはカンマや引用符を含められる"
+    fn test_quoted() {
+        let entry = Entry::parse("\",\",5,5,100,記号,読点,*,*,*,*,\",\",\",\",\",\"").unwrap();
+        assert_eq!(entry.surface, ",");
+        assert_eq!(entry.left_id, 5);
+        let feature = Feature::parse(entry.feature.as_ref().unwrap()).unwrap();
+        assert_eq!(feature.pos, ["記号", "読点", "*", "*"]);
+        assert_eq!(feature.base_form, ",");
+        assert_eq!(feature.pronunciation, ",");
+
+        let entry = Entry::parse("\"\"\"\",5,5,100,記号").unwrap();
+        assert_eq!(entry.surface, "\"");
+        let entry = Entry::parse("\"a\"\"b\",5,5,100").unwrap();
+        assert_eq!(entry.surface, "a\"b");
+    }
+
+    #[test]
+    // "素性の引用符の中の \"\" を戻し、引用符が正しくない素性はエラーにする"
+    fn test_quoted_feature() {
+        let feature = Feature::parse("記号,\"a\"\"b\",*").unwrap();
+        assert_eq!(feature.pos, ["記号", "a\"b", "*", "*"]);
+        assert!(Feature::parse("記号,\"a").is_err());
+        assert!(Feature::parse("記号,\"a\"b,*").is_err());
+        assert!(Features::new().insert("記号,\"a").is_err());
+    }
+
+    #[test]
+    // "CSV の一行に書き戻すと元の行に戻る"
+    fn test_display() {
+        let text = "\"1,000\"\"\",1,2,-3,名詞,\"数,詞\"";
+        let entry = Entry::parse(text).unwrap();
+        assert_eq!(entry.to_string(), text);
+        assert_eq!(Entry::parse(&entry.to_string()).unwrap(), entry);
+
+        let entry = Entry::parse("すもも,1,2,3").unwrap();
+        assert_eq!(entry.to_string(), "すもも,1,2,3");
+    }
+
+    #[test]
+    // "誤りのある行は行番号付きのエラーになる"
+    fn test_error() {
+        let lines = |text: &str| -> Vec<Option<usize>> {
+            entries(text)
+                .into_iter()
+                .map(|e| e.err().and_then(|e| e.line()))
+                .collect()
+        };
+        assert_eq!(lines("a,1,1,1\n\nb,1,x,1\n"), vec![None, Some(3)]);
+        assert_eq!(lines("a,1,1\n"), vec![Some(1)]);
+        assert_eq!(lines(",1,1,1\n"), vec![Some(1)]);
+        assert_eq!(lines("\"a\"b,1,1,1\n"), vec![Some(1)]);
+        assert_eq!(lines("a,1,1,1,\"x\"y\n"), vec![Some(1)]);
+        assert_eq!(lines("a,1,1,1\n\"b,1,1,1\n"), vec![None, Some(2)]);
+        assert_eq!(lines("a,1,1,99999\n"), vec![Some(1)]);
+    }
+
+    #[test]
+    // "引用符の中の改行は次の行につながる"
+    fn test_multiline() {
+        let text = "\"a\nb\",1,1,1\r\nc,1,1,x\n";
+        let result = entries(text);
+        assert_eq!(result[0].as_ref().unwrap().surface, "a\nb");
+        assert_eq!(result[1].as_ref().err().unwrap().line(), Some(3));
+    }
+
+    #[test]
+    // "文字コードを指定して読み込み、変換できない行は行番号付きのエラーになる"
+    fn test_charset() {
+        let text = b"\xA4\xB9,1,1,10,\xCC\xBE\xBB\xEC\n\xFF,1,1,10\n";
+        let result: Vec<_> = SourceReader::with_charset(&text[..], Charset::EucJp).collect();
+        let entry = result[0].as_ref().unwrap();
+        assert_eq!(entry.surface, "す");
+        assert_eq!(entry.feature.as_deref(), Some("名詞"));
+        assert_eq!(result[1].as_ref().err().unwrap().line(), Some(2));
+
+        let result: Vec<_> = SourceReader::new(&text[..]).collect();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].as_ref().err().unwrap().line(), Some(1));
+    }
+
+    #[test]
+    // "PrefixMap に登録する"
+    fn test_insert_into() {
+        let text = "す,1,1,10,名詞\nもも,1,1,20,名詞\n\"a,b\",1,1,5\n";
+        let mut dic = Trie::<u16, Info>::new();
+        let mut features = Features::new();
+        let reader = SourceReader::new(text.as_bytes());
+        let count = reader
+            .insert_into(&mut dic, Some(&mut features), None)
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(features.len(), 1);
+        assert_eq!(dic.get("もも").unwrap()[0].cost, 20);
+        assert_eq!(dic.get("a,b").unwrap()[0].feature, Info::NO_FEATURE);
+
+        let matrix = Matrix {
+            width: 1,
+            height: 2,
+            cost: vec![0; 2],
+        };
+        let reader = SourceReader::new(text.as_bytes());
+        let err = reader
+            .insert_into(&mut Trie::<u16, Info>::new(), None, Some(&matrix))
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(1));
+    }
+
+    #[test]
+    // "UniDic の列を IPADIC の列順の素性にする"
+    fn test_unidic() {
+        let text = "飲む,1234,1234,5000,動詞,一般,*,*,五段-マ行,終止形-一般,ノム,飲む,飲む,ノム,飲む,ノム,和,*,*,*,*,ノム,ノム,ノム,ノム,*,*,1,C1,*";
+        let entry = SourceFormat::Unidic.parse(text).unwrap().unwrap();
+        assert_eq!(entry.surface, "飲む");
+        assert_eq!(entry.cost, 5000);
+        let feature = Feature::parse(entry.feature.as_ref().unwrap()).unwrap();
+        assert_eq!(feature.pos, ["動詞", "一般", "*", "*"]);
+        assert_eq!(feature.conjugation_type, "五段-マ行");
+        assert_eq!(feature.conjugation_form, "終止形-一般");
+        assert_eq!(feature.base_form, "飲む");
+        assert_eq!(feature.reading, "ノム");
+        assert_eq!(feature.pronunciation, "ノム");
+
+        // 足りない列は `*` になる
+        let entry = SourceFormat::Unidic.parse("a,1,1,1,名詞").unwrap().unwrap();
+        assert_eq!(entry.feature.unwrap(), "名詞,*,*,*,*,*,*,*,*");
+    }
+
+    #[test]
+    // "Sudachi の列を IPADIC の列順の素性にし、解析に使わない単語は読み飛ばす"
+    fn test_sudachi() {
+        let text = "東京都,6,8,5320,東京都,名詞,固有名詞,地名,一般,*,*,トウキョウト,東京都,*,B,5/9,*,5/9,*\n\
+                    東京,-1,-1,0,東京,名詞,固有名詞,地名,一般,*,*,トウキョウ,東京,*,A,*,*,*,*\n\
+                    \"1,000\",5,5,100,\"1,000\",名詞,数詞,*,*,*,*,センエン,\"1,000\",*,A,*,*,*,*\n";
+        let mut reader = SourceReader::new(text.as_bytes()).with_format(SourceFormat::Sudachi);
+        let entry = reader.next().unwrap().unwrap();
+        assert_eq!(
+            (entry.surface.as_str(), entry.left_id, entry.right_id),
+            ("東京都", 6, 8)
+        );
+        assert_eq!(
+            entry.feature.unwrap(),
+            "名詞,固有名詞,地名,一般,*,*,東京都,トウキョウト,トウキョウト"
+        );
+        let entry = reader.next().unwrap().unwrap();
+        assert_eq!(reader.line(), 3);
+        assert_eq!(entry.surface, "1,000");
+        assert_eq!(
+            Feature::parse(entry.feature.as_ref().unwrap())
+                .unwrap()
+                .base_form,
+            "1,000"
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!(
+            "UniDic".parse::<SourceFormat>().unwrap(),
+            SourceFormat::Unidic
+        );
+        assert_eq!(
+            "sudachi".parse::<SourceFormat>().unwrap(),
+            SourceFormat::Sudachi
+        );
+        assert!("jumandic".parse::<SourceFormat>().is_err());
+    }
+}
@@ -1,12 +1,13 @@
-use crate::as_chars::AsChars;
+use crate::as_chars::{AsChars, CodeUnit};
 use crate::dictionary::SaveDict;
-use crate::prefix_map::PrefixMap;
+use crate::error::Result;
+use crate::prefix_map::{PrefixMap, PrefixMapInsert, PrefixMapMut};
 use std::io::Write;
 use std::marker::PhantomData;
 use std::time::Instant;
 
 pub trait Transform<M1, M2> {
-    fn transform(src: M1) -> M2;
+    fn transform(src: M1) -> Result<M2>;
 }
 
 pub enum TransformMap<M1, M2, Tr: Transform<M1, M2>> {
@@ -21,6 +22,13 @@ impl<M1: Default, M2, Tr: Transform<M1, M2>> Default for TransformMap<M1, M2, Tr
     }
 }
 
+/// 変換前のデータ構造から作る。読み込み専用の `M2` に変換するときは、`M1` に登録してから渡す。
+impl<M1, M2, Tr: Transform<M1, M2>> From<M1> for TransformMap<M1, M2, Tr> {
+    fn from(src: M1) -> Self {
+        Before(src, PhantomData)
+    }
+}
+
 impl<K, V, M1, M2, Tr> PrefixMap<K, V> for TransformMap<M1, M2, Tr>
 where
     M1: PrefixMap<K, V>,
@@ -44,10 +52,13 @@ where
     }
 
     #[inline]
-    fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[V])>
+    where
+        K: Copy,
+    {
         match self {
-            Before(x, _) => x.insert(key, value),
-            After(x) => x.insert(key, value),
+            Before(x, _) => x.longest_match(key),
+            After(x) => x.longest_match(key),
         }
     }
 
@@ -58,6 +69,88 @@ where
             After(x) => x.each_prefix(key, f),
         }
     }
+
+    #[inline]
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, f: F)
+    where
+        V: 'a,
+    {
+        match self {
+            Before(x, _) => x.each_entry(f),
+            After(x) => x.each_entry(f),
+        }
+    }
+
+    #[inline]
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        f: F,
+    ) where
+        K: CodeUnit,
+        V: 'a,
+    {
+        match self {
+            Before(x, _) => x.each_fuzzy(key, max_distance, f),
+            After(x) => x.each_fuzzy(key, max_distance, f),
+        }
+    }
+
+    #[inline]
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        prefix: T,
+        f: F,
+    ) where
+        V: 'a,
+    {
+        match self {
+            Before(x, _) => x.each_predictive(prefix, f),
+            After(x) => x.each_predictive(prefix, f),
+        }
+    }
+}
+
+impl<K, V, M1, M2, Tr> PrefixMapInsert<K, V> for TransformMap<M1, M2, Tr>
+where
+    M1: PrefixMapInsert<K, V>,
+    M2: PrefixMapInsert<K, V>,
+    Tr: Transform<M1, M2>,
+{
+    #[inline]
+    fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
+        match self {
+            Before(x, _) => x.insert(key, value),
+            After(x) => x.insert(key, value),
+        }
+    }
+}
+
+impl<K, V, M1, M2, Tr> PrefixMapMut<K, V> for TransformMap<M1, M2, Tr>
+where
+    M1: PrefixMapMut<K, V>,
+    M2: PrefixMapMut<K, V>,
+    Tr: Transform<M1, M2>,
+{
+    #[inline]
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<V>> {
+        match self {
+            Before(x, _) => x.remove(key),
+            After(x) => x.remove(key),
+        }
+    }
+
+    #[inline]
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        match self {
+            Before(x, _) => x.remove_value(key, value),
+            After(x) => x.remove_value(key, value),
+        }
+    }
 }
 
 impl<K, V, M1, M2, Tr> SaveDict<K, V> for TransformMap<M1, M2, Tr>
@@ -66,16 +159,16 @@ where
     M2: SaveDict<K, V>,
     Tr: Transform<M1, M2>,
 {
-    fn save_to_file<W: Write>(self, file: W) -> Self {
+    fn save_to_file<W: Write>(self, file: W) -> Result<Self> {
         let dic = match self {
             Before(x, _) => {
                 let start = Instant::now();
-                let x = Tr::transform(x);
+                let x = Tr::transform(x)?;
                 eprintln!("transform: {:?}", start.elapsed());
                 x
             }
             After(x) => x,
         };
-        After(dic.save_to_file(file))
+        dic.save_to_file(file).map(After)
     }
 }
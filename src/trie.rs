@@ -1,5 +1,6 @@
-use crate::as_chars::AsChars;
-use crate::prefix_map::PrefixMap;
+use crate::as_chars::{AsChars, CodeUnit};
+use crate::fuzzy::Levenshtein;
+use crate::prefix_map::{remove_one, take_values, PrefixMap, PrefixMapInsert, PrefixMapMut};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Node<K, V> {
@@ -69,6 +70,102 @@ impl<K: Copy + Ord, V> Node<K, V> {
         }
     }
 
+    /// キーのノードの値を `f` で取り除き、値も子もなくなったノードを削除する。
+    fn remove_rec<I: Iterator<Item = K>, R, F: FnOnce(&mut Vec<V>) -> Option<R>>(
+        &mut self,
+        mut iter: I,
+        f: F,
+    ) -> Option<R> {
+        let ch = match iter.next() {
+            Some(ch) => ch,
+            None => return f(&mut self.data),
+        };
+        let ix = self.search(ch).ok()?;
+        let result = self.children[ix].1.remove_rec(iter, f);
+        let child = &self.children[ix].1;
+        if child.data.is_empty() && child.children.is_empty() {
+            self.children.remove(ix);
+        }
+        result
+    }
+
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, key: &mut Vec<K>, f: &mut F) {
+        if !self.data.is_empty() {
+            f(key, &self.data[..]);
+        }
+        for (ch, child) in &self.children {
+            key.push(*ch);
+            child.each_entry(key, f);
+            key.pop();
+        }
+    }
+
+    /// このノード以下のキーと値を辞書順に渡す。`f` が `false` を返すと打ち切り、`false` を返す。
+    fn each_predictive<'a, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        key: &mut Vec<K>,
+        f: &mut F,
+    ) -> bool {
+        if !self.data.is_empty() && !f(key, &self.data[..]) {
+            return false;
+        }
+        for (ch, child) in &self.children {
+            key.push(*ch);
+            if !child.each_predictive(key, f) {
+                return false;
+            }
+            key.pop();
+        }
+        true
+    }
+
+    /// このノード以下で編集距離が上限に収まるキーを辞書順に渡す。
+    /// `f` が `false` を返すと打ち切り、`false` を返す。
+    fn each_fuzzy<'a, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: &mut Vec<K>,
+        lev: &mut Levenshtein<K>,
+        f: &mut F,
+    ) -> bool
+    where
+        K: CodeUnit,
+    {
+        if !self.data.is_empty() {
+            if let Some(distance) = lev.distance() {
+                if !f(key, distance, &self.data[..]) {
+                    return false;
+                }
+            }
+        }
+        for (ch, child) in &self.children {
+            let reachable = lev.push(*ch);
+            key.push(*ch);
+            let cont = !reachable || child.each_fuzzy(key, lev, f);
+            key.pop();
+            lev.pop();
+            if !cont {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[inline]
+    fn longest_match<I: Iterator<Item = K>>(&self, iter: I) -> Option<(usize, &[V])> {
+        let mut cursor = self;
+        let mut longest = None;
+        for (chix, ch) in iter.enumerate() {
+            match cursor.search(ch) {
+                Ok(ix) => cursor = &cursor.children[ix].1,
+                Err(_) => break,
+            }
+            if !cursor.data.is_empty() {
+                longest = Some((chix + 1, &cursor.data[..]));
+            }
+        }
+        longest
+    }
+
     #[inline]
     fn each_prefix<I: Iterator<Item = K>, F: FnMut(usize, &[V])>(&self, iter: I, mut f: F) {
         let mut cursor = self;
@@ -114,20 +211,84 @@ impl<K: Copy + Ord, V> PrefixMap<K, V> for Trie<K, V> {
         self.root.get(key.as_chars())
     }
 
+    #[inline]
+    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, f: F) {
+        self.root.each_prefix(key.as_chars(), f);
+    }
+
+    #[inline]
+    fn longest_match<T: AsChars<K>>(&self, key: T) -> Option<(usize, &[V])> {
+        self.root.longest_match(key.as_chars())
+    }
+
+    #[inline]
+    fn each_entry<'a, F: FnMut(&[K], &'a [V])>(&'a self, mut f: F)
+    where
+        V: 'a,
+    {
+        self.root.each_entry(&mut vec![], &mut f);
+    }
+
+    fn each_fuzzy<'a, T: AsChars<K>, F: FnMut(&[K], usize, &'a [V]) -> bool>(
+        &'a self,
+        key: T,
+        max_distance: usize,
+        mut f: F,
+    ) where
+        K: CodeUnit,
+        V: 'a,
+    {
+        let mut lev = Levenshtein::new(key.as_chars().collect(), max_distance);
+        self.root.each_fuzzy(&mut vec![], &mut lev, &mut f);
+    }
+
+    fn each_predictive<'a, T: AsChars<K>, F: FnMut(&[K], &'a [V]) -> bool>(
+        &'a self,
+        prefix: T,
+        mut f: F,
+    ) where
+        V: 'a,
+    {
+        let mut key = vec![];
+        let mut cursor = &self.root;
+        for ch in prefix.as_chars() {
+            match cursor.search(ch) {
+                Ok(ix) => cursor = &cursor.children[ix].1,
+                Err(_) => return,
+            }
+            key.push(ch);
+        }
+        cursor.each_predictive(&mut key, &mut f);
+    }
+}
+
+impl<K: Copy + Ord, V> PrefixMapInsert<K, V> for Trie<K, V> {
     #[inline]
     fn insert<T: AsChars<K>>(&mut self, key: T, value: V) {
         self.root.insert_rec(key.as_chars(), value);
     }
+}
 
+impl<K: Copy + Ord, V> PrefixMapMut<K, V> for Trie<K, V> {
     #[inline]
-    fn each_prefix<T: AsChars<K>, F: FnMut(usize, &[V])>(&self, key: T, f: F) {
-        self.root.each_prefix(key.as_chars(), f);
+    fn remove<T: AsChars<K>>(&mut self, key: T) -> Option<Vec<V>> {
+        self.root.remove_rec(key.as_chars(), take_values)
+    }
+
+    #[inline]
+    fn remove_value<T: AsChars<K>>(&mut self, key: T, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let remove = |data: &mut Vec<V>| remove_one(data, value);
+        self.root.remove_rec(key.as_chars(), remove).is_some()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prefix_map::owned_keys;
     type Dic16<T> = Trie<u16, T>;
     type Dic8<T> = Trie<u8, T>;
 
@@ -330,4 +491,44 @@ mod tests {
             assert_eq!(vec, vec![(1, vec![4, 5]), (3, vec![1])]);
         }
     }
+
+    #[test]
+    // "登録したキーと値を辞書順に列挙する"
+    fn test_each_entry() {
+        let mut dic = Dic16::new();
+        dic.insert("もも", 1);
+        dic.insert("すもも", 2);
+        dic.insert("す", 3);
+        dic.insert("もも", 4);
+
+        let mut vec = vec![];
+        dic.each_entry(|key, data| {
+            vec.push((String::from_utf16(key).unwrap(), data.to_owned()));
+        });
+        let expected = owned_keys(vec![
+            ("す", vec![3]),
+            ("すもも", vec![2]),
+            ("もも", vec![1, 4]),
+        ]);
+        assert_eq!(vec, expected);
+    }
+
+    #[test]
+    // "キーと値を取り除き、空になったノードを削除する"
+    fn test_remove() {
+        let mut dic = Dic16::new();
+        dic.insert("すもも", 1);
+        dic.insert("すし", 2);
+        dic.insert("すし", 3);
+
+        assert!(dic.remove_value("すし", &2));
+        assert_eq!(dic.get("すし"), Some(&[3][..]));
+        assert_eq!(dic.remove("す"), None);
+        assert_eq!(dic.remove("すもも"), Some(vec![1]));
+        assert_eq!(dic.root.children.len(), 1);
+        assert_eq!(dic.root.children[0].1.children.len(), 1);
+        assert!(dic.remove_value("すし", &3));
+        assert!(dic.root.children.is_empty());
+        assert_eq!(dic.count(), 0);
+    }
 }
@@ -1,5 +1,6 @@
 use crate::as_chars::AsUsize;
 use crate::double_array::DoubleArray;
+use crate::error::Result;
 #[allow(unused_imports)]
 use crate::prefix_map::PrefixMap;
 use crate::search_cache::*;
@@ -13,8 +14,8 @@ pub type Trie2DAMap<K, V> = TransformMap<Trie<K, V>, DoubleArray<K, V, NoCache>,
 pub enum Trie2DoubleArray {}
 
 impl<K: AsUsize, V> Transform<Trie<K, V>, DoubleArray<K, V, NoCache>> for Trie2DoubleArray {
-    fn transform(trie: Trie<K, V>) -> DoubleArray<K, V, NoCache> {
-        transform(trie)
+    fn transform(trie: Trie<K, V>) -> Result<DoubleArray<K, V, NoCache>> {
+        Ok(transform(trie))
     }
 }
 
@@ -118,6 +119,7 @@ fn calc_stats_rec<K, V>(node: &Node<K, V>, table: &mut Vec<u8>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prefix_map::PrefixMapInsert;
     type Trie8<V> = super::super::trie::Trie<u8, V>;
 
     macro_rules! combine_cache {
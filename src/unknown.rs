@@ -0,0 +1,384 @@
+use crate::error::{Error, Result};
+use crate::feature::Features;
+use crate::info::Info;
+use crate::matrix::Matrix;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+
+#[inline]
+fn read_int<T: FromStr>(str: Option<&str>, name: &str) -> Result<T> {
+    let str = str.ok_or_else(|| Error::format(format!("{}が取得できません", name)))?;
+    str.trim()
+        .parse()
+        .map_err(|_| Error::format(format!("{:?}を数値に変換できません", str)))
+}
+
+#[inline]
+fn read_code_point(str: &str) -> Result<u32> {
+    let hex = str.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(hex, 16)
+        .map_err(|_| Error::format(format!("{:?}を文字コードに変換できません", str)))
+}
+
+/// カテゴリ数の上限。互換カテゴリをビットマスクで持つため 32 までとする。
+const MAX_CATEGORIES: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+pub struct CharCategory {
+    pub name: String,
+    /// 辞書に単語があっても未知語処理を行う
+    pub invoke: bool,
+    /// 同じカテゴリの文字をまとめて未知語にする
+    pub group: bool,
+    /// 1 文字から `length` 文字までの未知語を作る
+    pub length: usize,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub(crate) struct CharType {
+    pub(crate) primary: u8,
+    mask: u32,
+}
+
+impl CharType {
+    #[inline]
+    pub(crate) fn is_compatible(self, category: u8) -> bool {
+        self.mask & (1 << category) != 0
+    }
+}
+
+/// MeCab 形式の `char.def` と `unk.def` による未知語の定義
+#[derive(Serialize, Deserialize)]
+pub struct Unknown {
+    categories: Vec<CharCategory>,
+    infos: Vec<Vec<Info>>,
+    features: Features,
+    table: Vec<CharType>,
+    default: CharType,
+}
+
+impl Unknown {
+    pub fn load_from_file(char_def: &str, unk_def: &str) -> Result<Unknown> {
+        let char_file = File::open(char_def).map_err(|e| Error::from(e).in_file(char_def))?;
+        let unk_file = File::open(unk_def).map_err(|e| Error::from(e).in_file(unk_def))?;
+        let mut unknown =
+            Unknown::read_char_def(BufReader::new(char_file)).map_err(|e| e.in_file(char_def))?;
+        unknown
+            .read_unk_def(BufReader::new(unk_file))
+            .map_err(|e| e.in_file(unk_def))?;
+        Ok(unknown)
+    }
+
+    pub fn from_reader<R1: BufRead, R2: BufRead>(char_def: R1, unk_def: R2) -> Result<Unknown> {
+        let mut unknown = Unknown::read_char_def(char_def)?;
+        unknown.read_unk_def(unk_def)?;
+        Ok(unknown)
+    }
+
+    fn read_char_def<R: BufRead>(file: R) -> Result<Unknown> {
+        let mut categories: Vec<CharCategory> = vec![];
+        let mut ranges = vec![];
+        for (ix, line) in file.lines().enumerate() {
+            let line = line.map_err(|e| Error::from(e).at_line(ix + 1))?;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut row = line.split_whitespace();
+            let head = row.next().unwrap();
+            if head.starts_with("0x") || head.starts_with("0X") {
+                let range = match head.find("..") {
+                    Some(pos) => read_code_point(&head[..pos])
+                        .and_then(|start| Ok((start, read_code_point(&head[pos + 2..])?))),
+                    None => read_code_point(head).map(|ch| (ch, ch)),
+                };
+                let (start, end) = range.map_err(|e| e.at_line(ix + 1))?;
+                if start > end {
+                    let message = format!("範囲の始まりが終わりより後ろです ({})", head);
+                    return Err(Error::format(message).at_line(ix + 1));
+                }
+                let names: Vec<String> = row.map(str::to_owned).collect();
+                if names.is_empty() {
+                    return Err(Error::format("カテゴリが指定されていません。").at_line(ix + 1));
+                }
+                ranges.push((start, end, names, ix + 1));
+            } else {
+                if categories.iter().any(|c| c.name == head) {
+                    let message = format!("{} が重複しています。", head);
+                    return Err(Error::format(message).at_line(ix + 1));
+                }
+                if categories.len() >= MAX_CATEGORIES {
+                    return Err(Error::format("カテゴリが多すぎます。").at_line(ix + 1));
+                }
+                let at_line = |e: Error| e.at_line(ix + 1);
+                let invoke: u8 = read_int(row.next(), "INVOKE").map_err(at_line)?;
+                let group: u8 = read_int(row.next(), "GROUP").map_err(at_line)?;
+                let length = read_int(row.next(), "LENGTH").map_err(at_line)?;
+                categories.push(CharCategory {
+                    name: head.to_owned(),
+                    invoke: invoke != 0,
+                    group: group != 0,
+                    length,
+                });
+            }
+        }
+
+        let find = |name: &str, line: usize| match categories.iter().position(|c| c.name == name) {
+            Some(ix) => Ok(ix as u8),
+            None => {
+                let message = format!("{} は定義されていないカテゴリです。", name);
+                Err(Error::format(message).at_line(line))
+            }
+        };
+        let default_ix = match categories.iter().position(|c| c.name == "DEFAULT") {
+            Some(ix) => ix as u8,
+            None => return Err(Error::format("DEFAULT カテゴリが定義されていません。")),
+        };
+        let default = CharType {
+            primary: default_ix,
+            mask: 1 << default_ix,
+        };
+
+        let size = ranges.iter().map(|r| r.1 as usize + 1).max().unwrap_or(0);
+        let mut table = vec![default; size];
+        // 後の行の定義で上書きする
+        for (start, end, names, line) in ranges {
+            let primary = find(&names[0], line)?;
+            let mut mask = 0;
+            for name in &names {
+                mask |= 1 << find(name, line)?;
+            }
+            for ty in &mut table[start as usize..=end as usize] {
+                *ty = CharType { primary, mask };
+            }
+        }
+
+        let infos = categories.iter().map(|_| vec![]).collect();
+        Ok(Unknown {
+            categories,
+            infos,
+            features: Features::new(),
+            table,
+            default,
+        })
+    }
+
+    fn read_unk_def<R: BufRead>(&mut self, file: R) -> Result<()> {
+        for (ix, line) in file.lines().enumerate() {
+            let line = line.map_err(|e| Error::from(e).at_line(ix + 1))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut row = line.splitn(5, ',');
+            let name = row.next().unwrap();
+            let category = match self.categories.iter().position(|c| c.name == name) {
+                Some(category) => category,
+                None => {
+                    let message = format!("{} は定義されていないカテゴリです。", name);
+                    return Err(Error::format(message).at_line(ix + 1));
+                }
+            };
+            let at_line = |e: Error| e.at_line(ix + 1);
+            let left = read_int(row.next(), "LeftID").map_err(at_line)?;
+            let right = read_int(row.next(), "RightID").map_err(at_line)?;
+            let cost = read_int(row.next(), "コスト").map_err(at_line)?;
+            let feature = match row.next() {
+                Some(feature) => self.features.insert(feature).map_err(at_line)?,
+                None => Info::NO_FEATURE,
+            };
+            self.infos[category].push(Info::with_feature(left, right, cost, feature));
+        }
+        for (category, infos) in self.categories.iter().zip(&self.infos) {
+            if infos.is_empty() {
+                let message = format!("{} の未知語定義がありません。", category.name);
+                return Err(Error::format(message));
+            }
+        }
+        Ok(())
+    }
+
+    /// 未知語定義の文脈IDがコスト行列の範囲に収まっているかを確かめる。
+    pub fn check_matrix(&self, matrix: &Matrix) -> Result<()> {
+        for (category, infos) in self.categories.iter().zip(&self.infos) {
+            for info in infos {
+                if let Err(e) = matrix.check_info(info) {
+                    let message = format!("{} の未知語定義: {}", category.name, e);
+                    return Err(Error::format(message));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn categories(&self) -> &[CharCategory] {
+        &self.categories
+    }
+
+    #[inline]
+    pub(crate) fn category(&self, ty: CharType) -> &CharCategory {
+        &self.categories[ty.primary as usize]
+    }
+
+    #[inline]
+    pub(crate) fn infos(&self, ty: CharType) -> &[Info] {
+        &self.infos[ty.primary as usize]
+    }
+
+    #[inline]
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    #[inline]
+    pub(crate) fn char_type(&self, ch: char) -> CharType {
+        match self.table.get(ch as usize) {
+            Some(&ty) => ty,
+            None => self.default,
+        }
+    }
+}
+
+impl Default for Unknown {
+    /// 辞書にない位置だけを一文字の固有名詞として扱う
+    fn default() -> Self {
+        let default = CharType {
+            primary: 0,
+            mask: 1,
+        };
+        Unknown {
+            categories: vec![CharCategory {
+                name: "DEFAULT".to_owned(),
+                invoke: false,
+                group: false,
+                length: 1,
+            }],
+            infos: vec![vec![Info::new(1288, 1288, 10000)]],
+            features: Features::new(),
+            table: vec![],
+            default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze, IntoString, Matrix, PrefixMapInsert, Trie};
+
+    const CHAR_DEF: &str = "
+DEFAULT 0 1 0
+HIRAGANA 0 0 2  # ひらがな
+KATAKANA 1 1 0
+KANJI 0 0 2
+
+0x3041..0x309F HIRAGANA
+0x30A1..0x30FF KATAKANA
+0x30FC KATAKANA HIRAGANA
+0x4E00..0x9FFF KANJI
+";
+
+    const UNK_DEF: &str = "\
+DEFAULT,1,1,1000,記号
+HIRAGANA,1,1,2000,名詞
+KATAKANA,1,1,1000,名詞
+KANJI,1,1,3000,名詞
+";
+
+    fn unknown() -> Unknown {
+        Unknown::from_reader(CHAR_DEF.as_bytes(), UNK_DEF.as_bytes()).unwrap()
+    }
+
+    fn words<K: IntoString>(analyzed: &crate::Analyzed<'_, K>) -> Vec<String> {
+        analyzed.iter().map(|t| K::into_string(t.word)).collect()
+    }
+
+    #[test]
+    fn test_char_type() {
+        let unknown = unknown();
+        let names = |ch| &unknown.category(unknown.char_type(ch)).name[..];
+        assert_eq!(names('あ'), "HIRAGANA");
+        assert_eq!(names('ア'), "KATAKANA");
+        assert_eq!(names('ー'), "KATAKANA");
+        assert_eq!(names('a'), "DEFAULT");
+        assert!(unknown.char_type('ー').is_compatible(1));
+        assert!(!unknown.char_type('ア').is_compatible(1));
+    }
+
+    #[test]
+    fn test_group() {
+        let (unknown, features) = (unknown(), Features::new());
+        let matrix = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0; 4],
+        };
+        {
+            let mut dic = Trie::<u16, Info>::new();
+            dic.insert("の", Info::new(1, 1, 100));
+            let analyzed = analyze("コーヒーの", &dic, &matrix, &unknown, &features).unwrap();
+            assert_eq!(words(&analyzed), vec!["コーヒー", "の"]);
+            let feature = analyzed.iter().next().unwrap().feature.unwrap();
+            assert_eq!(feature.pos[0], "名詞");
+        }
+        {
+            let mut dic = Trie::<u8, Info>::new();
+            dic.insert("の", Info::new(1, 1, 100));
+            let analyzed = analyze("コーヒーの", &dic, &matrix, &unknown, &features).unwrap();
+            assert_eq!(words(&analyzed), vec!["コーヒー", "の"]);
+        }
+    }
+
+    #[test]
+    fn test_default() {
+        let (unknown, features) = (Unknown::default(), Features::new());
+        let dic = Trie::<char, Info>::new();
+        let matrix = Matrix {
+            width: 1289,
+            height: 1289,
+            cost: vec![0; 1289 * 1289],
+        };
+        let analyzed = analyze::<char, _>("漢字", &dic, &matrix, &unknown, &features).unwrap();
+        assert_eq!(words(&analyzed), vec!["漢", "字"]);
+        assert_eq!(analyzed.cost, 20000);
+    }
+
+    #[test]
+    // "未知語定義の文脈IDをコスト行列と照合する"
+    fn test_check_matrix() {
+        let small = Matrix {
+            width: 2,
+            height: 2,
+            cost: vec![0; 4],
+        };
+        assert!(unknown().check_matrix(&small).is_ok());
+        let err = Unknown::default().check_matrix(&small).err().unwrap();
+        assert!(err.to_string().starts_with("DEFAULT の未知語定義: "));
+    }
+
+    #[test]
+    // "定義の誤りは行番号付きのエラーになる"
+    fn test_error() {
+        let unk_def = "DEFAULT,1,1,1000\nHIRAGANA,1,x,2000\n";
+        let err = Unknown::from_reader(CHAR_DEF.as_bytes(), unk_def.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(2));
+        assert_eq!(err.to_string(), "2行目: \"x\"を数値に変換できません");
+
+        let char_def = "DEFAULT 0 1 0\n0x3041 UNKNOWN\n";
+        let err = Unknown::from_reader(char_def.as_bytes(), UNK_DEF.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(2));
+
+        // 逆向きの範囲
+        let char_def = "DEFAULT 0 1 0\nNUMERIC 1 1 0\n0x0030..0x0020 NUMERIC\n";
+        let err = Unknown::from_reader(char_def.as_bytes(), UNK_DEF.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(3));
+        assert!(err.to_string().contains("0x0030..0x0020"));
+    }
+}